@@ -0,0 +1,104 @@
+// Times the solver on examples/fire_music.hrp, which has no diagram,
+// and the packed harp operations the search leans on against the plain
+// Harp ones they replaced, over every pair of harps the piece asks for.
+// Run with cargo bench.
+use harp_pedal_solver::astar::AstarState;
+use harp_pedal_solver::candidate::find_candidates;
+use harp_pedal_solver::config::SolverConfig;
+use harp_pedal_solver::parse::parse;
+use harp_pedal_solver::prelude::*;
+use harp_pedal_solver::solve::get_chords;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::hint::black_box;
+use std::mem::size_of;
+use std::time::{Duration, Instant};
+
+const RUNS: u32 = 10;
+
+fn hash<T: Hash>(t: T) -> u64 {
+    let mut h = DefaultHasher::new();
+    t.hash(&mut h);
+    h.finish()
+}
+
+// What the search does with a state's pedals and a beat's target.
+fn harp_ops(pairs: &[(Harp, Harp)]) -> usize {
+    let mut out = 0;
+    for (a, b) in pairs {
+        let next = update_harp(*a, *b);
+        out += harp_changes(*a, *b, 0..7).len();
+        out += num_same(next) + num_crossed(next);
+        out += hash(next) as usize;
+    }
+    out
+}
+
+fn packed_ops(pairs: &[(PackedHarp, PackedHarp)]) -> usize {
+    let mut out = 0;
+    for (a, b) in pairs {
+        let next = a.update(*b);
+        out += a.changes(*b, ALL_PEDALS).len();
+        out += next.num_same() + next.num_crossed();
+        out += hash(next) as usize;
+    }
+    out
+}
+
+fn best<T>(mut f: impl FnMut() -> T) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            black_box(f());
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    let parsed = parse(include_str!("../examples/fire_music.hrp")).unwrap();
+    let input = MusicInput::from(&parsed);
+    let config = SolverConfig::default();
+
+    let harps: Vec<Harp> = get_chords(&input)
+        .unwrap()
+        .into_iter()
+        .flat_map(|b| b.targets)
+        .collect();
+    let pairs: Vec<(Harp, Harp)> = harps
+        .iter()
+        .flat_map(|a| harps.iter().map(move |b| (*a, *b)))
+        .collect();
+    let packed: Vec<(PackedHarp, PackedHarp)> = pairs
+        .iter()
+        .map(|(a, b)| ((*a).into(), (*b).into()))
+        .collect();
+    let before = best(|| harp_ops(black_box(&pairs)));
+    let after = best(|| packed_ops(black_box(&packed)));
+    println!(
+        "harp ops on {} pairs: Harp {before:?}, PackedHarp {after:?}, {:.1}x",
+        pairs.len(),
+        before.as_secs_f64() / after.as_secs_f64()
+    );
+    println!(
+        "state: {} bytes, pedals {} bytes rather than {}",
+        size_of::<AstarState>(),
+        size_of::<PackedHarp>(),
+        size_of::<Harp>()
+    );
+
+    let mut times = Vec::with_capacity(RUNS as usize);
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        let candidates = find_candidates(&input, &config).unwrap();
+        times.push(start.elapsed());
+        assert!(!candidates.is_empty());
+    }
+    let total: Duration = times.iter().sum();
+    println!(
+        "fire_music: mean {:?}, best {:?} over {RUNS} runs",
+        total / RUNS,
+        times.iter().min().unwrap()
+    );
+}
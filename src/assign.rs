@@ -1,96 +1,360 @@
 use crate::prelude::*;
-use crate::util::unravel_paths;
 use itertools::Itertools;
-use trees::{Forest, Tree};
-
-// What pedals can be used to play a note,
-// that aren't being used already for a different note?
-fn viable_pedals(pc: PitchClass, used: &[Name]) -> Vec<Note> {
-    // Each pich is playable on at most two pedals.
-    let mut viable = Vec::with_capacity(2);
-    for n in pc_to_notes(pc) {
-        if !used.contains(&n.name) {
-            viable.push(n);
+use std::collections::HashMap;
+use std::fmt;
+
+// 1, 6 and 11 can only be played on one string, so are placed first.
+const ORDER: [PitchClass; 12] = [1, 6, 11, 0, 2, 3, 4, 5, 7, 8, 9, 10];
+
+fn string_bit(note: Note) -> u8 {
+    1 << name_to_usize(note.name)
+}
+
+// Every way of giving each pitch class a string of its own, found by
+// backtracking one pitch class at a time, and yielded as each is found.
+pub struct Assignments {
+    preset: Harp,
+    pcs: Vec<PitchClass>,
+    // The note each pitch class so far is played as.
+    chosen: Vec<Note>,
+    // Which of each pitch class's notes to try next.
+    next: Vec<usize>,
+    // Strings taken, by the preset or a chosen note, a bit for each.
+    used: u8,
+}
+
+impl Iterator for Assignments {
+    type Item = Harp;
+
+    fn next(&mut self) -> Option<Harp> {
+        // Nothing left to assign, the preset alone is the one assignment.
+        if self.pcs.is_empty() {
+            return self.next.pop().map(|_| self.preset);
+        }
+        while let Some(i) = self.next.last_mut() {
+            let depth = self.chosen.len();
+            let Some(note) = pc_to_notes(self.pcs[depth]).get(*i).copied()
+            else {
+                self.next.pop();
+                if let Some(n) = self.chosen.pop() {
+                    self.used &= !string_bit(n);
+                }
+                continue;
+            };
+            *i += 1;
+            if self.used & string_bit(note) != 0 {
+                continue;
+            }
+            if depth + 1 == self.pcs.len() {
+                let mut out = self.preset;
+                for n in self.chosen.iter().chain([&note]) {
+                    set_pedal(&mut out, *n);
+                }
+                return Some(out);
+            }
+            self.chosen.push(note);
+            self.used |= string_bit(note);
+            self.next.push(0);
         }
+        None
     }
-    viable
-}
-
-// Given a note, and a list of used notes,
-// create a child for every possible new pedal setting.
-fn create_children(
-    forest: &mut Forest<Note>,
-    new_note: PitchClass,
-    used: &[Name],
-) {
-    let pedals = viable_pedals(new_note, used);
-    for pedal in pedals {
-        forest.push_back(Tree::new(pedal));
+}
+
+// Two different notes can't share a string.
+fn preset_clashes(preset: &[Note]) -> bool {
+    preset
+        .iter()
+        .unique()
+        .map(|n| n.name)
+        .duplicates()
+        .next()
+        .is_some()
+}
+
+// The pitch classes that still need a string, a bit for each.
+// Pitches already sounded by a preset need no string of their own.
+fn pc_mask(preset: &[Note], notes: &[PitchClass]) -> u16 {
+    let fixed = preset.iter().fold(0, |m, n| m | 1 << note_to_pc(*n));
+    notes.iter().fold(0, |m, pc| m | 1 << pc) & !fixed
+}
+
+// Lazily list the possible assignments of notes, none if the preset clashes.
+pub fn assignments(preset: &[Note], notes: &[PitchClass]) -> Assignments {
+    let mask = pc_mask(preset, notes);
+    Assignments {
+        preset: notes_to_harp(preset),
+        pcs: ORDER.into_iter().filter(|pc| mask & 1 << pc != 0).collect(),
+        chosen: vec![],
+        next: if preset_clashes(preset) {
+            vec![]
+        } else {
+            vec![0]
+        },
+        used: preset.iter().fold(0, |m, n| m | string_bit(*n)),
     }
 }
 
-// Grow child nodes into trees, assigning remaining notes
-// and not reusing used pedals.
-fn grow_children(
-    forest: &mut Forest<Note>,
-    remaining_notes: &[PitchClass],
-    used: &[Name],
-) {
-    for mut branch in forest.iter_mut() {
-        let mut more_used = used.to_owned();
-        more_used.push(branch.data().name);
-        branch.append(assignment_builder(remaining_notes, &more_used));
+// List all possible assignments of notes.
+pub fn assign(preset: &[Note], notes: &[PitchClass]) -> Option<Vec<Harp>> {
+    let out = assignments(preset, notes).collect_vec();
+    if out.is_empty() {
+        None
+    } else {
+        Some(out)
     }
 }
 
-// If we expect grandchildren, kill barren children.
-fn kill_barren_children(forest: &mut Forest<Note>) {
-    for mut branch in forest.iter_mut() {
-        if branch.has_no_child() {
-            branch.detach();
-        };
+// A preset's strings and settings, in order, and the pitches left over.
+type ChordKey = (Vec<(usize, Accidental)>, u16);
+
+// Assignments already listed, since chords are often repeated.
+#[derive(Default)]
+pub struct AssignCache(HashMap<ChordKey, Option<Vec<Harp>>>);
+
+impl AssignCache {
+    // As assign, but each distinct preset and set of pitches only once.
+    pub fn assign(
+        &mut self,
+        preset: &[Note],
+        notes: &[PitchClass],
+    ) -> Option<Vec<Harp>> {
+        let fixed = preset
+            .iter()
+            .map(|n| (name_to_usize(n.name), n.accidental))
+            .unique()
+            .sorted_by_key(|(i, a)| (*i, *a as u8))
+            .collect_vec();
+        let key = (fixed, pc_mask(preset, notes));
+        self.0
+            .entry(key)
+            .or_insert_with(|| assign(preset, notes))
+            .clone()
+    }
+}
+
+// Why a chord can't be played: more notes than strings they can use.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Conflict {
+    pub fixed: Vec<Note>,
+    pub pitches: Vec<PitchClass>,
+    pub strings: Vec<Name>,
+    // Fixed notes that would make the chord playable if respelled.
+    pub respell: Vec<Note>,
+    // For a glissando, strings are those that can't sound any of the notes.
+    pub gliss: bool,
+}
+
+fn pc_name(pc: PitchClass) -> String {
+    pc_to_notes(pc)
+        .iter()
+        .rev()
+        .map(|n| n.to_string())
+        .join("/")
+}
+
+// "the G string", "the G or A string", "the F, G or A string"
+fn string_list(strings: &[Name]) -> String {
+    match strings {
+        [] => "no string".to_string(),
+        [s] => format!("the {s} string"),
+        [init @ .., last] => format!(
+            "the {} or {last} string",
+            init.iter().map(|s| s.to_string()).join(", ")
+        ),
     }
 }
 
-// Build a tree of all possible assignments of notes,
-// without reusing used pedals.
-// All terminal nodes represent a complete assignment.
-fn assignment_builder(notes: &[PitchClass], used: &[Name]) -> Forest<Note> {
-    let mut forest = Forest::<Note>::new();
-    if let Some((new_note, remaining_notes)) = notes.split_first() {
-        create_children(&mut forest, *new_note, used);
-        if !remaining_notes.is_empty() {
-            grow_children(&mut forest, remaining_notes, used);
-            kill_barren_children(&mut forest);
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let notes = self
+            .fixed
+            .iter()
+            .map(|n| format!("*{n}"))
+            .chain(self.pitches.iter().map(|pc| pc_name(*pc)))
+            .join(", ");
+        if self.gliss {
+            return write!(
+                f,
+                "a glissando on {notes} has nothing for {}",
+                string_list(&self.strings)
+            );
+        }
+        let all = if self.fixed.len() + self.pitches.len() > 2 {
+            " all"
+        } else {
+            ""
+        };
+        write!(f, "{notes}{all} need {}", string_list(&self.strings))?;
+        if !self.respell.is_empty() {
+            let respell = self
+                .respell
+                .iter()
+                .map(|n| format!("*{n} as {}", enharmonic(*n)))
+                .join(" or ");
+            write!(f, "; try respelling {respell}")?;
         }
+        Ok(())
     }
-    forest
 }
 
-// List all possible assignments of notes.
-pub fn assign(preset: &[Note], notes: &[PitchClass]) -> Option<Vec<Harp>> {
-    let mut sanitized = Vec::new();
-    // 1, 6, 11 can only be accessed by one pedal
-    for x in [1, 6, 11, 0, 2, 3, 4, 5, 7, 8, 9, 10] {
-        if notes.contains(&x) {
-            sanitized.push(x);
+fn enharmonic(note: Note) -> Note {
+    pc_to_notes(note_to_pc(note))
+        .into_iter()
+        .find(|n| *n != note)
+        .unwrap_or(note)
+}
+
+// Which strings can sound the note or pitch class?
+#[derive(Copy, Clone)]
+enum Request {
+    Fixed(Note),
+    Pitch(PitchClass),
+}
+
+fn strings_for(request: Request) -> Vec<Name> {
+    match request {
+        Request::Fixed(n) => vec![n.name],
+        Request::Pitch(pc) => pc_to_notes(pc).iter().map(|n| n.name).collect(),
+    }
+}
+
+// By Hall's theorem, a chord is impossible exactly when some set of
+// requests has fewer strings between them than requests.
+// Search smallest sets first, so the report is as short as possible.
+fn smallest_conflict(requests: &[Request]) -> Option<Vec<Request>> {
+    for k in 1..=requests.len() {
+        for set in requests.iter().copied().combinations(k) {
+            let strings =
+                set.iter().flat_map(|r| strings_for(*r)).unique().count();
+            if strings < k {
+                return Some(set);
+            }
         }
     }
-    let out = if notes.is_empty() {
-        vec![update_harp([None; 7], notes_to_harp(preset))]
-    } else {
-        unravel_paths(assignment_builder(
-            &sanitized,
-            &preset.iter().map(|n| n.name).collect_vec(),
-        ))
+    None
+}
+
+// If the chord can't be assigned, explain why.
+pub fn explain(preset: &[Note], notes: &[PitchClass]) -> Option<Conflict> {
+    let fixed = preset.iter().copied().unique().collect_vec();
+    let fixed_pcs = fixed.iter().map(|n| note_to_pc(*n)).collect_vec();
+    let requests = fixed
         .iter()
-        .map(|v| notes_to_harp(v))
-        .map(|h| update_harp(h, notes_to_harp(preset)))
-        .collect_vec()
+        .map(|n| Request::Fixed(*n))
+        .chain(
+            notes
+                .iter()
+                .unique()
+                .filter(|pc| !fixed_pcs.contains(pc))
+                .map(|pc| Request::Pitch(*pc)),
+        )
+        .collect_vec();
+    let set = smallest_conflict(&requests)?;
+    let mut conflict = Conflict {
+        fixed: vec![],
+        pitches: vec![],
+        strings: vec![],
+        respell: vec![],
+        gliss: false,
     };
-    if out.is_empty() && !notes.is_empty() {
+    for r in set {
+        match r {
+            Request::Fixed(n) => conflict.fixed.push(n),
+            Request::Pitch(pc) => conflict.pitches.push(pc),
+        }
+        for s in strings_for(r) {
+            if !conflict.strings.contains(&s) {
+                conflict.strings.push(s);
+            }
+        }
+    }
+    conflict.strings.sort_by_key(|s| name_to_usize(*s));
+    for n in conflict.fixed.iter() {
+        if enharmonic(*n) == *n {
+            continue;
+        }
+        let others = preset.iter().filter(|p| *p != n).copied().collect_vec();
+        let mut pcs = notes.to_vec();
+        pcs.push(note_to_pc(*n));
+        if assign(&others, &pcs).is_some() {
+            conflict.respell.push(*n);
+        }
+    }
+    Some(conflict)
+}
+
+// The settings each string can take in a glissando: fixed notes as written,
+// other strings on any of the notes, enharmonic doubles and all.
+fn gliss_options(
+    preset: &[Note],
+    notes: &[PitchClass],
+) -> [Vec<Accidental>; 7] {
+    let mut pcs = notes.to_vec();
+    pcs.extend(preset.iter().map(|n| note_to_pc(*n)));
+    let mut out: [Vec<Accidental>; 7] = Default::default();
+    for (i, options) in out.iter_mut().enumerate() {
+        let fixed = preset
+            .iter()
+            .filter(|n| name_to_usize(n.name) == i)
+            .map(|n| n.accidental)
+            .unique()
+            .collect_vec();
+        *options = match fixed[..] {
+            [] => [Flat, Natural, Sharp]
+                .into_iter()
+                .filter(|a| {
+                    idx_to_note(i, Some(*a))
+                        .is_some_and(|n| pcs.contains(&note_to_pc(n)))
+                })
+                .collect_vec(),
+            [a] => vec![a],
+            _ => vec![],
+        };
+    }
+    out
+}
+
+// Every full setting in which all seven strings sound one of the notes.
+pub fn assign_gliss(
+    preset: &[Note],
+    notes: &[PitchClass],
+) -> Option<Vec<Harp>> {
+    let out = gliss_options(preset, notes)
+        .into_iter()
+        .multi_cartesian_product()
+        .map(|v| {
+            let mut harp = [None; 7];
+            for (i, a) in v.into_iter().enumerate() {
+                harp[i] = Some(a);
+            }
+            harp
+        })
+        .collect_vec();
+    if out.is_empty() {
         None
     } else {
         Some(out)
     }
 }
+
+// If no setting glisses on just these notes, say which strings can't.
+pub fn explain_gliss(
+    preset: &[Note],
+    notes: &[PitchClass],
+) -> Option<Conflict> {
+    let strings = gliss_options(preset, notes)
+        .iter()
+        .positions(|o| o.is_empty())
+        .filter_map(|i| idx_to_note(i, Some(Natural)).map(|n| n.name))
+        .collect_vec();
+    if strings.is_empty() {
+        return None;
+    }
+    Some(Conflict {
+        fixed: preset.iter().copied().unique().collect_vec(),
+        pitches: notes.iter().copied().unique().collect_vec(),
+        strings,
+        respell: vec![],
+        gliss: true,
+    })
+}
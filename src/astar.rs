@@ -1,42 +1,196 @@
-use crate::cli::CONST;
-use crate::cost::{astar_cost, astar_heuristic};
+use crate::config::SolverConfig;
+use crate::cost::{astar_cost, astar_heuristic, pedal_diff};
 use crate::prelude::*;
 use itertools::Itertools;
-use pathfinding::directed::astar::{astar_bag, AstarSolution};
+use pathfinding::directed::astar::{astar, astar_bag, AstarSolution};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::iter;
+use std::rc::Rc;
+
+// Times in a state are kept to 32 bits, since states are hashed so often.
+// Longer ones saturate, so RING_ON still rings on.
+fn narrow(x: usize) -> u32 {
+    u32::try_from(x).unwrap_or(u32::MAX)
+}
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct Change {
     pub note: Note,
-    pub time: usize,
+    pub time: u32,
+    // The same time, in milliseconds.
+    pub millis: u32,
 }
 
 impl Change {
     pub fn new(note: Note) -> Change {
-        Change { note, time: 0 }
+        Change {
+            note,
+            time: 0,
+            millis: 0,
+        }
     }
 
-    pub fn advance(&mut self) {
-        self.time += 1
+    pub fn advance(&mut self, ticks: usize, millis: usize) {
+        self.time = self.time.saturating_add(narrow(ticks));
+        self.millis = self.millis.saturating_add(narrow(millis));
+    }
+}
+
+// Everything the search needs to know about one beat of music.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Beat {
+    // The ways the beat can be spelled.
+    pub targets: Vec<Harp>,
+    // How long until the next beat, in ticks.
+    pub duration: usize,
+    // The same, in milliseconds at the current tempo, if one was given.
+    pub millis: Option<usize>,
+    // Where its notes lie, if we know.
+    pub register: Option<Register>,
+    // How long its notes are written to last, in ticks.
+    pub sustain: usize,
+    // Whether every string is damped before it is played.
+    pub damp: bool,
+    // Changes it must have, or feet that must stay still.
+    pub directives: Directives,
+    // A pedal diagram the harp must match when it is played.
+    pub checkpoint: Option<Harp>,
+    // Pitch classes doubled on purpose, for a bisbigliando.
+    pub bisb: Vec<PitchClass>,
+    // Whether it is a glissando, where every doubled string is wanted.
+    pub gliss: bool,
+}
+
+impl Beat {
+    pub fn new(
+        targets: Vec<Harp>,
+        duration: usize,
+        millis: Option<usize>,
+        register: Option<Register>,
+        sustain: usize,
+        damp: bool,
+        directives: Directives,
+    ) -> Beat {
+        Beat {
+            targets,
+            duration,
+            millis,
+            register,
+            sustain,
+            damp,
+            directives,
+            checkpoint: None,
+            bisb: vec![],
+            gliss: false,
+        }
+    }
+
+    // Only spellings that agree with the diagram are left to search.
+    // The targets are still just the notes played, the diagram is
+    // checked against the whole setting in succ.
+    pub fn with_checkpoint(mut self, checkpoint: Option<Harp>) -> Beat {
+        if let Some(c) = checkpoint {
+            self.targets.retain(|t| meet(*t, c).is_some());
+        }
+        self.checkpoint = checkpoint;
+        self
+    }
+
+    // Whether the pedals are set as any diagram here says.
+    pub fn allows(&self, pedals: Harp) -> bool {
+        self.checkpoint
+            .is_none_or(|c| meet(pedals, c) == Some(pedals))
+    }
+
+    pub fn with_bisb(mut self, bisb: Vec<PitchClass>) -> Beat {
+        self.bisb = bisb;
+        self
+    }
+
+    pub fn with_gliss(mut self, gliss: bool) -> Beat {
+        self.gliss = gliss;
+        self
+    }
+}
+
+// A string that rings until it is damped.
+pub const RING_ON: usize = usize::MAX;
+
+// What happens on the way into a beat.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Step {
+    // Since the previous beat, in ticks and milliseconds.
+    // Without a tempo there's no telling how fast the feet must be.
+    pub elapsed: usize,
+    pub millis: Option<usize>,
+    // How long this beat lasts, and how long its strings ring, in ticks.
+    pub duration: usize,
+    pub sustain: usize,
+    pub damp: bool,
+}
+
+// How we get to beat i, where beat mid.len() is the final setting.
+// Treat the start as a quarter note before the music.
+pub fn step(mid: &[Beat], i: usize, config: &SolverConfig) -> Step {
+    let (elapsed, millis) = match i {
+        0 => (QUARTER, Some(DEFAULT_TEMPO.millis(QUARTER))),
+        _ => (mid[i - 1].duration, mid[i - 1].millis),
+    };
+    match mid.get(i) {
+        Some(beat) => Step {
+            elapsed,
+            millis,
+            duration: beat.duration,
+            // An undamped string rings on past its written length.
+            sustain: if config.let_ring {
+                RING_ON
+            } else {
+                beat.sustain + config.decay * QUARTER
+            },
+            damp: beat.damp,
+        },
+        None => Step {
+            elapsed,
+            millis,
+            duration: QUARTER,
+            sustain: 0,
+            // Once the music is over, the strings can be damped.
+            damp: true,
+        },
     }
 }
 
 #[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
 pub struct AstarState {
     pub beat: usize,
-    pub pedals: Harp,
-    // (last note, how long ago it was)
+    // Packed, since states are hashed so often. See harp() for a Harp.
+    pub pedals: PackedHarp,
+    // (last note, how many ticks ago it was)
     pub last_left: Option<Change>,
     pub last_right: Option<Change>,
-    pub early: [bool; 7],
+    // Pedals changed before they were needed, a bit for each.
+    pub early: u8,
+    // Ticks since the previous beat.
+    pub elapsed: u32,
+    // How long each pedal's strings ring on into the next beat,
+    // u32::MAX for RING_ON.
+    pub ringing: [u32; 7],
+    // Pedals just changed while their strings were ringing, as for early.
+    pub buzz: u8,
 }
 
-fn advance_memory(m: Option<Change>) -> Option<Change> {
+fn advance_memory(
+    m: Option<Change>,
+    forget_after: usize,
+    elapsed: usize,
+    millis: Option<usize>,
+) -> Option<Change> {
     if let Some(mut change) = m {
-        if change.time >= CONST.forget_after {
+        if change.time as usize >= forget_after * QUARTER {
             None
         } else {
-            change.advance();
+            change.advance(elapsed, millis.unwrap_or(0));
             Some(change)
         }
     } else {
@@ -48,21 +202,70 @@ impl AstarState {
     pub fn new(pedals: Harp) -> AstarState {
         AstarState {
             beat: 0,
-            pedals,
+            pedals: pedals.into(),
             last_left: None,
             last_right: None,
-            early: [false; 7],
+            early: 0,
+            elapsed: 0,
+            ringing: [0; 7],
+            buzz: 0,
         }
     }
 
+    pub fn harp(&self) -> Harp {
+        self.pedals.into()
+    }
+
+    pub fn is_early(&self, idx: usize) -> bool {
+        self.early & (1 << idx) != 0
+    }
+
+    pub fn is_buzzing(&self, idx: usize) -> bool {
+        self.buzz & (1 << idx) != 0
+    }
+
     pub fn set_early(&mut self, name: Name) {
-        self.early[name_to_usize(name)] = true;
+        self.early |= 1 << name_to_usize(name);
     }
 
     // Unset notes that are no longer early
     pub fn unset_early(&mut self, target: Harp) {
-        for (i, b) in self.early.iter_mut().enumerate() {
-            *b = *b && target[i].is_none();
+        for (i, t) in target.iter().enumerate() {
+            if t.is_some() {
+                self.early &= !(1 << i);
+            }
+        }
+    }
+
+    pub fn set_buzz(&mut self, name: Name) {
+        let i = name_to_usize(name);
+        if self.ringing[i] > 0 {
+            self.buzz |= 1 << i;
+        } else {
+            self.buzz &= !(1 << i);
+        }
+    }
+
+    // Strings played in this beat ring for its sustain, as do strings
+    // doubling them, and everything that rings past the next beat is
+    // remembered.
+    pub fn ring(&mut self, target: Harp, step: Step) {
+        let played = |i: usize| {
+            idx_to_note(i, self.pedals.get(i)).is_some_and(|n| {
+                target.iter().enumerate().any(|(j, t)| {
+                    idx_to_note(j, *t)
+                        .is_some_and(|m| note_to_pc(m) == note_to_pc(n))
+                })
+            })
+        };
+        let played: [bool; 7] = std::array::from_fn(played);
+        for (i, r) in self.ringing.iter_mut().enumerate() {
+            if played[i] {
+                *r = (*r).max(narrow(step.sustain));
+            }
+            if *r != u32::MAX {
+                *r = r.saturating_sub(narrow(step.duration));
+            }
         }
     }
 
@@ -71,36 +274,56 @@ impl AstarState {
         left: Option<Note>,
         right: Option<Note>,
         target: Harp,
+        forget_after: usize,
+        step: Step,
     ) {
         self.beat += 1;
+        self.elapsed = narrow(step.elapsed);
+        self.buzz = 0;
+        if step.damp {
+            self.ringing = [0; 7];
+        }
         match left {
             Some(note) => {
-                set_pedal(&mut self.pedals, note);
+                self.pedals.set(note);
                 self.last_left = Some(Change::new(note));
                 self.set_early(note.name);
+                self.set_buzz(note.name);
             }
             None => {
-                self.last_left = advance_memory(self.last_left);
+                self.last_left = advance_memory(
+                    self.last_left,
+                    forget_after,
+                    step.elapsed,
+                    step.millis,
+                );
             }
         }
         match right {
             Some(note) => {
-                set_pedal(&mut self.pedals, note);
+                self.pedals.set(note);
                 self.last_right = Some(Change::new(note));
                 self.set_early(note.name);
+                self.set_buzz(note.name);
             }
             None => {
-                self.last_right = advance_memory(self.last_right);
+                self.last_right = advance_memory(
+                    self.last_right,
+                    forget_after,
+                    step.elapsed,
+                    step.millis,
+                );
             }
         }
         self.unset_early(target);
+        self.ring(target, step);
     }
 }
 
 // What changes can we make with our left foot?
 // None means changing nothing is an option, empty means there are no options.
 fn left_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
-    let l_changes = harp_changes(state.pedals, target, 0..=2);
+    let l_changes = state.pedals.changes(target.into(), LEFT_PEDALS);
     match &l_changes[..] {
         // Can change a single pedal, if undetermined
         [] => {
@@ -109,7 +332,7 @@ fn left_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
             for (j, n) in target[0..=2].iter().enumerate() {
                 if n.is_none() {
                     for new in [Some(Flat), Some(Natural), Some(Sharp)] {
-                        if state.pedals[j] != new {
+                        if state.pedals.get(j) != new {
                             new_lefts.push(idx_to_note(j, new));
                         }
                     }
@@ -128,7 +351,7 @@ fn left_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
 }
 
 fn right_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
-    let r_changes = harp_changes(state.pedals, target, 3..=6);
+    let r_changes = state.pedals.changes(target.into(), RIGHT_PEDALS);
     match &r_changes[..] {
         // Can change a single pedal, if undetermined
         [] => {
@@ -137,7 +360,7 @@ fn right_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
             for (j, n) in target[3..=6].iter().enumerate() {
                 if n.is_none() {
                     for new in [Some(Flat), Some(Natural), Some(Sharp)] {
-                        if state.pedals[j] != new {
+                        if state.pedals.get(j + 3) != new {
                             new_rights.push(idx_to_note(j + 3, new));
                         }
                     }
@@ -155,15 +378,48 @@ fn right_targets(state: AstarState, target: Harp) -> Vec<Option<Note>> {
     }
 }
 
+// Has the foot had time to get from its last change to this one?
+// Changes that have been forgotten were long enough ago.
+fn fast_enough(
+    old: Option<Change>,
+    new: Option<Note>,
+    millis: Option<usize>,
+    config: &SolverConfig,
+) -> bool {
+    match (old, new, millis) {
+        (Some(old), Some(new), Some(millis)) => {
+            old.millis as usize + millis
+                >= config.min_change_time
+                    + config.pedal_move_time * pedal_diff(old.note, new)
+        }
+        _ => true,
+    }
+}
+
 // let left_is_early = l_changes.len() > 1;
 // let right_is_early = r_changes.len() > 1;
-fn get_targets(state: AstarState, target: Harp) -> Vec<AstarState> {
+// Directives are hard constraints, so break them and the option is gone.
+fn get_targets(
+    state: AstarState,
+    target: Harp,
+    step: Step,
+    directives: &Directives,
+    config: &SolverConfig,
+) -> Vec<AstarState> {
     let mut out: Vec<AstarState> = vec![];
-    let l_changes = left_targets(state, target);
-    let r_changes = right_targets(state, target);
+    let l_changes = left_targets(state, target)
+        .into_iter()
+        .filter(|l| fast_enough(state.last_left, *l, step.millis, config))
+        .filter(|l| directives.allows(Foot::Left, *l))
+        .collect_vec();
+    let r_changes = right_targets(state, target)
+        .into_iter()
+        .filter(|r| fast_enough(state.last_right, *r, step.millis, config))
+        .filter(|r| directives.allows(Foot::Right, *r))
+        .collect_vec();
     for (left, right) in l_changes.into_iter().cartesian_product(r_changes) {
         let mut new_state = state;
-        new_state.advance(left, right, target);
+        new_state.advance(left, right, target, config.forget_after, step);
         out.push(new_state);
     }
     out
@@ -172,13 +428,17 @@ fn get_targets(state: AstarState, target: Harp) -> Vec<AstarState> {
 fn target_costs(
     state: AstarState,
     targets: &[Harp],
+    beat: Option<&Beat>,
+    step: Step,
+    directives: &Directives,
+    config: &SolverConfig,
 ) -> Vec<(AstarState, usize)> {
     let mut out = vec![];
     for target in targets {
         out.append(
-            &mut get_targets(state, *target)
+            &mut get_targets(state, *target, step, directives, config)
                 .into_iter()
-                .map(|t| (t, astar_cost(state, t)))
+                .map(|t| (t, astar_cost(state, t, beat, config)))
                 .collect_vec(),
         )
     }
@@ -187,40 +447,83 @@ fn target_costs(
 
 fn succ(
     state: AstarState,
-    mid: &[Vec<Harp>],
+    mid: &[Beat],
     end: Harp,
+    config: &SolverConfig,
 ) -> Vec<(AstarState, usize)> {
     let i = state.beat;
+    if i > mid.len() {
+        return vec![];
+    }
+    let step = step(mid, i, config);
     if i < mid.len() {
-        target_costs(state, &mid[i])
-    } else if i == mid.len() {
-        target_costs(state, &[end])
+        let beat = &mid[i];
+        let directives = &beat.directives;
+        target_costs(state, &beat.targets, Some(beat), step, directives, config)
+            .into_iter()
+            .filter(|(next, _)| beat.allows(next.harp()))
+            .collect_vec()
     } else {
-        vec![]
+        let free = Directives::default();
+        target_costs(state, &[end], None, step, &free, config)
     }
 }
 
+// The search begins at a virtual source, None, whose successors are
+// every start the diagram allows, at no cost.
+// Stopping short of the end, at beat stop, the heuristic toward the
+// cheapest of ahead, what the music after stop may need, is charged on
+// arriving there.
+fn source_succ(
+    state: Option<AstarState>,
+    starts: &[AstarState],
+    mid: &[Beat],
+    end: Harp,
+    stop: usize,
+    ahead: &[Harp],
+    config: &SolverConfig,
+) -> Vec<(Option<AstarState>, usize)> {
+    let arrive = |next: AstarState| match next.beat == stop {
+        true => ahead
+            .iter()
+            .map(|a| astar_heuristic(next, *a, config))
+            .min()
+            .unwrap_or(0),
+        false => 0,
+    };
+    match state {
+        None => starts.iter().map(|s| (Some(*s), 0)).collect_vec(),
+        Some(state) => succ(state, mid, end, config)
+            .into_iter()
+            .map(|(next, c)| (Some(next), c + arrive(next)))
+            .collect_vec(),
+    }
+}
+
+// One search then finds the best start along with its plan, and keeps
+// tied starts together.
 fn min_score_via_astar(
-    start: Harp,
-    mid: &[Vec<Harp>],
+    starts: &[AstarState],
+    mid: &[Beat],
     end: Harp,
-) -> Option<(AstarSolution<AstarState>, usize)> {
+    config: &SolverConfig,
+) -> Option<(AstarSolution<Option<AstarState>>, usize)> {
+    let stop = mid.len() + 1;
     astar_bag(
         // Initial state
-        &AstarState::new(start),
+        &None,
         // Given we are at state, where can we go?
-        |&state| succ(state, mid, end),
+        |state| source_succ(*state, starts, mid, end, stop, &[], config),
         // Heuristic giving a lower bound on the distance p to end
-        |&state| astar_heuristic(state, end),
+        |state| state.map_or(0, |s| astar_heuristic(s, end, config)),
         // success
-        |&state| state.beat > mid.len(),
+        |state| state.is_some_and(|s| s.beat >= stop),
     )
 }
 
 fn possible_starts(state: Harp) -> Vec<Harp> {
     let mut choices =
-        iter::repeat(vec![Some(Flat), Some(Natural), Some(Sharp)])
-            .take(7)
+        iter::repeat_n(vec![Some(Flat), Some(Natural), Some(Sharp)], 7)
             .collect_vec();
     for (i, m) in state.iter().enumerate() {
         if m.is_some() {
@@ -242,28 +545,178 @@ fn possible_starts(state: Harp) -> Vec<Harp> {
     out
 }
 
+// Every full setting the diagram allows, as states before the music.
+pub fn start_states(diagram: Harp) -> Vec<AstarState> {
+    possible_starts(diagram)
+        .into_iter()
+        .map(AstarState::new)
+        .collect_vec()
+}
+
 pub fn find_solutions(
     start: Harp,
-    mid: &[Vec<Harp>],
+    mid: &[Beat],
     end: Harp,
+    config: &SolverConfig,
 ) -> (Vec<Vec<AstarState>>, usize) {
-    let mut best_score = usize::MAX;
-    let mut best_choice = vec![];
-    for s in possible_starts(start) {
-        if let Some((astar, score)) = min_score_via_astar(s, mid, end) {
-            if score < best_score {
-                best_score = score;
-                best_choice = astar.into_iter().collect_vec();
-                // Allow tied options from a diffeent pedal setting
-                // } else if score == best_score {
-                //     best_choice.append(&mut astar.into_iter().collect_vec());
+    let starts = start_states(start);
+    let Some((astar, score)) = min_score_via_astar(&starts, mid, end, config)
+    else {
+        return (vec![], usize::MAX);
+    };
+    let mut out = vec![];
+    for path in astar {
+        // Without the source, the start, and the final setting.
+        let mut path = path.into_iter().flatten().collect_vec();
+        path.pop();
+        out.push(path.into_iter().skip(1).collect_vec());
+    }
+    (out, score)
+}
+
+// Plans on from any of the states, which may be partway through the
+// music, as far as beat stop, past the last beat for the final setting.
+// Short of that, the plan is costed as if heading for one of ahead.
+// Only one of any tied plans is kept, the same one every time.
+pub fn find_window(
+    starts: &[AstarState],
+    mid: &[Beat],
+    stop: usize,
+    end: Harp,
+    ahead: &[Harp],
+    config: &SolverConfig,
+) -> Option<(Vec<AstarState>, usize)> {
+    let (path, score) = astar(
+        &None,
+        |state| source_succ(*state, starts, mid, end, stop, ahead, config),
+        |state| state.map_or(0, |s| astar_heuristic(s, end, config)),
+        |state| state.is_some_and(|s| s.beat >= stop),
+    )?;
+    Some((path.into_iter().flatten().collect_vec(), score))
+}
+
+// A partial path, sharing its parent with the other paths through it,
+// so a path is freed once nothing still open leads on from it.
+struct Label {
+    state: Option<AstarState>,
+    cost: usize,
+    parent: Option<Rc<Label>>,
+}
+
+// The path to label, without the source.
+fn unwind(label: &Label) -> Vec<AstarState> {
+    let mut path = label.state.into_iter().collect_vec();
+    let mut parent = &label.parent;
+    while let Some(p) = parent {
+        path.extend(p.state);
+        parent = &p.parent;
+    }
+    path.reverse();
+    path
+}
+
+// Two plans are the same if they start alike and change each pedal the
+// same way, no matter exactly when, or which pedal is changed first.
+// As in a candidate, changes before the first beat are part of the
+// starting diagram, unless it gave that pedal.
+fn plan_key(
+    path: &[AstarState],
+    diagram: Harp,
+) -> (Harp, [Vec<Accidental>; 7]) {
+    let start = update_harp(path[1].harp(), diagram);
+    let settings =
+        iter::once(start.into()).chain(path[1..].iter().map(|s| s.pedals));
+    let mut pedals: [Vec<Accidental>; 7] = Default::default();
+    for (a, b) in settings.tuple_windows() {
+        for note in a.changes(b, ALL_PEDALS) {
+            pedals[name_to_usize(note.name)].push(note.accidental);
+        }
+    }
+    (start, pedals)
+}
+
+// The k cheapest meaningfully different paths, cheapest first.
+// From the same virtual source as min_score_via_astar, each state may be
+// expanded up to k times, so the n-th time we reach the goal is the n-th
+// cheapest path. Paths through a state expanded k times are dropped.
+fn k_best_via_astar(
+    diagram: Harp,
+    starts: &[AstarState],
+    mid: &[Beat],
+    end: Harp,
+    config: &SolverConfig,
+    k: usize,
+) -> Vec<(Vec<AstarState>, usize)> {
+    let stop = mid.len() + 1;
+    let mut open = HashMap::new();
+    let mut heap = BinaryHeap::new();
+    let mut expanded: HashMap<Option<AstarState>, usize> = HashMap::new();
+    let mut keys = HashSet::new();
+    let mut out = vec![];
+    let source = Label {
+        state: None,
+        cost: 0,
+        parent: None,
+    };
+    open.insert(0, Rc::new(source));
+    heap.push(Reverse((0, 0)));
+    let mut pushed = 1;
+    while let Some(Reverse((_, idx))) = heap.pop() {
+        let Some(label) = open.remove(&idx) else {
+            continue;
+        };
+        let times = expanded.entry(label.state).or_insert(0);
+        if *times >= k {
+            continue;
+        }
+        *times += 1;
+        if label.state.is_some_and(|s| s.beat >= stop) {
+            let path = unwind(&label);
+            if keys.insert(plan_key(&path, diagram)) {
+                out.push((path, label.cost));
+                if out.len() == k {
+                    break;
+                }
+            }
+            continue;
+        }
+        let next_states =
+            source_succ(label.state, starts, mid, end, stop, &[], config);
+        for (next, c) in next_states {
+            if expanded.get(&next).is_some_and(|t| *t >= k) {
+                continue;
             }
+            let cost = label.cost + c;
+            let h = next.map_or(0, |s| astar_heuristic(s, end, config));
+            heap.push(Reverse((cost + h, pushed)));
+            let next = Label {
+                state: next,
+                cost,
+                parent: Some(Rc::clone(&label)),
+            };
+            open.insert(pushed, Rc::new(next));
+            pushed += 1;
         }
     }
+    out
+}
+
+// Like find_solutions, but returns up to k distinct plans with their costs.
+pub fn find_k_solutions(
+    start: Harp,
+    mid: &[Beat],
+    end: Harp,
+    config: &SolverConfig,
+    k: usize,
+) -> Vec<(Vec<AstarState>, usize)> {
+    let starts = start_states(start);
     let mut out = vec![];
-    for mut path in best_choice {
+    for (mut path, cost) in
+        k_best_via_astar(start, &starts, mid, end, config, k)
+    {
+        // Without the start and the final setting.
         path.pop();
-        out.push(path.into_iter().skip(1).collect_vec());
+        out.push((path.into_iter().skip(1).collect_vec(), cost));
     }
-    (out, best_score)
+    out
 }
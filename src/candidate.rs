@@ -1,43 +1,187 @@
+use itertools::Itertools;
 use log::info;
+use std::iter;
 
 use crate::{
+    assign::Conflict,
+    astar::{find_window, start_states, Beat},
+    config::SolverConfig,
+    cost::{ledger_total, plan_ledger},
     prelude::*,
-    solve::{get_pedal_changes, get_spellings},
+    solve::{get_chords, get_k_spellings, get_pedal_changes, get_spellings},
 };
 
+// Only the notes asked for, not every string the pedals set.
+fn refine_spelling(spelling: &mut [Harp], input: &MusicInput) {
+    // For each beat
+    for (s, m) in spelling.iter_mut().zip(&input.music) {
+        let (this, any) = &m.this_any;
+        // For each pitch
+        for (j, d) in s.iter_mut().enumerate() {
+            if let Some(note) = idx_to_note(j, *d) {
+                if !this.contains(&note) && !any.contains(&note_to_pc(note)) {
+                    *d = None;
+                }
+            }
+        }
+    }
+}
+
+// The plan through these settings, costed per beat by the search that
+// found it, or by its ledger if not given.
+fn build_candidate(
+    input: &MusicInput,
+    s: Vec<Harp>,
+    chords: &[Beat],
+    config: &SolverConfig,
+    cost: Option<usize>,
+) -> Candidate {
+    let diagram = update_harp(
+        [Some(Flat); 7],
+        update_harp(input.goal, update_harp(full_initial(&s), input.diagram)),
+    );
+    let destination = update_harp(
+        update_harp([Some(Flat); 7], update_harps(input.diagram, &s)),
+        input.goal,
+    );
+    let pedals = get_pedal_changes(diagram, &s, destination);
+    let mut spelling = s;
+    refine_spelling(&mut spelling, input);
+    let mut targets = spelling.clone();
+    targets.push(destination);
+    let ledger = plan_ledger(diagram, &targets, chords, &pedals, config);
+    let cost = cost.unwrap_or_else(|| ledger_total(&ledger));
+    Candidate {
+        diagram,
+        destination,
+        spelling,
+        pedals,
+        cost: cost / input.music.len(),
+        ledger,
+    }
+}
+
 pub fn find_candidates(
     input: &MusicInput,
-) -> Result<Vec<Candidate>, Vec<usize>> {
+    config: &SolverConfig,
+) -> Result<Vec<Candidate>, Vec<(usize, Conflict)>> {
+    info!("Managing enharmonic spellings...");
+    let chords = get_chords(input)?;
+    let (spellings, cost) = get_spellings(input, &chords, config);
+    Ok(spellings
+        .into_iter()
+        .map(|s| build_candidate(input, s, &chords, config, Some(cost)))
+        .collect_vec())
+}
+
+// Up to k meaningfully different candidates, best first.
+pub fn find_k_candidates(
+    input: &MusicInput,
+    config: &SolverConfig,
+    k: usize,
+) -> Result<Vec<Candidate>, Vec<(usize, Conflict)>> {
     info!("Managing enharmonic spellings...");
-    let (spellings, cost) = get_spellings(input)?;
-    let average_cost = cost / input.music.len();
-    let mut candidates: Vec<CandidateBuilder> =
-        Vec::with_capacity(spellings.len());
-    for s in spellings {
-        let mut candidate = CandidateBuilder::new();
-        candidate.set_diagram(update_harp(
-            [Some(Flat); 7],
-            update_harp(
-                input.goal,
-                update_harp(full_initial(&s), input.diagram),
-            ),
-        ));
-        candidate.set_destination(update_harp(
-            update_harp([Some(Flat); 7], update_harps(input.diagram, &s)),
-            input.goal,
-        ));
-        candidate.set_spelling(s);
-        candidate.set_pedals(get_pedal_changes(&candidate));
-        candidate.refine_spelling(input);
-        candidates.push(candidate);
+    let chords = get_chords(input)?;
+    let spellings = get_k_spellings(input, &chords, config, k);
+    Ok(spellings
+        .into_iter()
+        .map(|(s, cost)| build_candidate(input, s, &chords, config, Some(cost)))
+        .collect_vec())
+}
+
+// How much of a long piece to plan at once, in measures, and how many
+// of those to plan again with the next window rather than commit to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct Window {
+    pub measures: usize,
+    pub overlap: usize,
+}
+
+// What the music still to come may need: each spelling of its next beat,
+// and for the pedals that leaves open, the first setting every spelling
+// of a later beat agrees on, or the goal.
+fn ahead(chords: &[Beat], goal: Harp) -> Vec<Harp> {
+    let Some((next, later)) = chords.split_first() else {
+        return vec![goal];
+    };
+    let later = agreed(later, goal);
+    next.targets
+        .iter()
+        .map(|t| update_harp(later, *t))
+        .collect_vec()
+}
+
+fn agreed(chords: &[Beat], goal: Harp) -> Harp {
+    let mut out = goal;
+    let mut open = [true; 7];
+    for beat in chords {
+        for (i, o) in open.iter_mut().enumerate().filter(|(_, o)| **o) {
+            match beat.targets.iter().map(|t| t[i]).unique().collect_vec()[..] {
+                [None] => continue,
+                [Some(a)] => out[i] = Some(a),
+                _ => out[i] = None,
+            }
+            *o = false;
+        }
     }
+    out
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum WindowError {
+    Conflicts(Vec<(usize, Conflict)>),
+    // The first measure of a window with no way on from the plan before it.
+    Stuck(usize),
+}
 
-    let mut out = Vec::with_capacity(candidates.len());
-    for mut c in candidates {
-        c.set_cost(average_cost);
-        if let Some(new) = c.try_init() {
-            out.push(new)
+// Plans a window at a time, keeping all but its overlap and sliding on,
+// so the search never holds more than a window of the piece.
+// Every window carries on from exactly where the last one was committed.
+pub fn find_windowed_candidates(
+    input: &MusicInput,
+    measure_lengths: &[usize],
+    window: Window,
+    config: &SolverConfig,
+) -> Result<Vec<Candidate>, WindowError> {
+    info!("Managing enharmonic spellings...");
+    let chords = get_chords(input).map_err(WindowError::Conflicts)?;
+    // The first beat of each measure, then the end of the music.
+    let bounds = iter::once(0)
+        .chain(measure_lengths.iter().scan(0, |sum, len| {
+            *sum += len;
+            Some(*sum)
+        }))
+        .collect_vec();
+    let slide = window.measures.saturating_sub(window.overlap).max(1);
+    let mut starts = start_states(input.diagram);
+    let mut spelling = Vec::with_capacity(chords.len());
+    let mut m = 0;
+    loop {
+        let done = m + window.measures >= measure_lengths.len();
+        let (stop, end, ahead) = if done {
+            (chords.len() + 1, input.goal, vec![])
+        } else {
+            let stop = bounds[m + window.measures];
+            (stop, [None; 7], ahead(&chords[stop..], input.goal))
+        };
+        let Some((path, _)) =
+            find_window(&starts, &chords, stop, end, &ahead, config)
+        else {
+            return Err(WindowError::Stuck(m));
+        };
+        // States are after each beat, the first is where we started.
+        if done {
+            let played = &path[1..path.len() - 1];
+            spelling.extend(played.iter().map(|s| s.harp()));
+            break;
         }
+        let commit = bounds[m + slide] - path[0].beat;
+        spelling.extend(path[1..=commit].iter().map(|s| s.harp()));
+        starts = vec![path[commit]];
+        m += slide;
     }
-    Ok(out)
+    // Each window was searched alone, so only the ledger has the total.
+    Ok(vec![build_candidate(
+        input, spelling, &chords, config, None,
+    )])
 }
@@ -1,22 +1,26 @@
-use clap::Parser;
+use crate::config::*;
+use crate::lever::{parse_tuning, Tuning};
+use clap::builder::RangedU64ValueParser;
+use clap::{Parser, ValueEnum};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
-use lazy_static::lazy_static;
 use std::path::PathBuf;
 
-// const SHOW: usize = 3;
-const CROSS_STRING_COST: usize = 1200;
-const DOUBLE_STRING_COST: usize = 100;
-const EARLY_CHANGE_COST: usize = 300;
-const FORGET_AFTER: usize = 4;
-const QUICK_CHANGE_COST: usize = 30;
-const QUICK_CHANGE_DECAY: usize = 10;
-const PEDAL_COST: usize = 1000;
-const PEDAL_DISTANCE_COST: usize = 1;
+const SHOW: usize = 1;
+const OVERLAP: usize = 1;
+
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, ValueEnum)]
+pub enum Format {
+    /// Readable, for a terminal.
+    #[default]
+    Text,
+    /// For other programs, in the schema described in src/json.rs.
+    Json,
+}
 
 #[derive(Parser)]
 #[command(author, version, about)]
 pub struct Cli {
-    /// Input file in .hrp format.
+    /// Input file in .hrp, MusicXML (.musicxml, .xml) or JSON (.json) format.
     pub file: PathBuf,
     /// Write output to FILE.
     #[arg(short, long, value_name = "FILE")]
@@ -24,14 +28,74 @@ pub struct Cli {
     /// Output a PDF. Requires lilypond.
     #[arg(long)]
     pub pdf: bool,
-    // /// Limit how many possibilities are shown. To show all, set show = 0.
-    // #[arg(long, default_value_t = SHOW, value_name = "INT")]
-    // pub show: usize,
+    /// Output MusicXML with pedal markings.
+    #[arg(long)]
+    pub xml: bool,
+    /// Keep the spellings written in MusicXML input, as if marked with *.
+    #[arg(long)]
+    pub keep_spelling: bool,
+    /// How to print the pedalings found.
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+    /// Print an itemised breakdown of each pedaling's cost.
+    #[arg(long)]
+    pub explain: bool,
+    /// How many meaningfully different pedalings to show, best first.
+    #[arg(
+        long,
+        default_value_t = SHOW,
+        value_name = "INT",
+        value_parser = RangedU64ValueParser::<usize>::new().range(1..)
+    )]
+    pub show: usize,
+    /// Start each section where the one before it ends, rather than afresh.
+    /// Only the best pedaling of each section is carried into the next.
+    #[arg(long)]
+    pub chain: bool,
+    /// Plan MEASURES at a time, keeping all but the overlap of each before
+    /// moving on, for pieces too long to plan at once. Finds one pedaling.
+    /// With --explain, also says how far it is from planning it all at once.
+    #[arg(long, value_name = "MEASURES")]
+    pub window: Option<usize>,
+    /// How many measures of each window to plan again with the next.
+    #[arg(long, default_value_t = OVERLAP, value_name = "MEASURES")]
+    pub overlap: usize,
+    /// Solve for a lever harp, tuned in Eb or as TUNING, with ^ for flat
+    /// and - for natural strings (eg --lever=---|----).
+    #[arg(
+        long,
+        value_name = "TUNING",
+        num_args = 0..=1,
+        default_missing_value = "--^|^--^",
+        value_parser = parse_tuning
+    )]
+    pub lever: Option<Tuning>,
+    /// Write the best pedaling back into the .hrp input, or into FILE
+    /// (--annotate=FILE), with its diagrams, its changes as {...} and its
+    /// spellings as *.
+    #[arg(long, value_name = "FILE", num_args = 0..=1, require_equals = true)]
+    pub annotate: Option<Option<PathBuf>>,
+    /// Check a hand-written pedaling rather than solving, from the
+    /// changes written in the music (eg {F# Bb}) or from PLAN
+    /// (--verify=PLAN), in the JSON that --format json writes.
+    #[arg(long, value_name = "PLAN", num_args = 0..=1, require_equals = true)]
+    pub verify: Option<Option<PathBuf>>,
     #[command(flatten)]
     pub verbose: Verbosity<InfoLevel>,
+    /// Let strings ring until damped with /, not just --decay past their
+    /// durations.
+    #[arg(long)]
+    pub let_ring: bool,
+    /// How much to penalize changing a pedal while its strings ring.
+    #[arg(long, default_value_t = BUZZ_COST, value_name = "INT")]
+    pub buzz_cost: usize,
     /// How much to penalize crossed strings (eg E# and Fb).
     #[arg(long, default_value_t = CROSS_STRING_COST, value_name = "INT")]
     pub cross_string_cost: usize,
+    /// How many quarter notes a string rings past its written length,
+    /// unless damped with /.
+    #[arg(long, default_value_t = DECAY, value_name = "INT")]
+    pub decay: usize,
     /// How much to penalize doubled strings (eg E# and F).
     #[arg(long, default_value_t = DOUBLE_STRING_COST, value_name = "INT")]
     pub double_string_cost: usize,
@@ -41,10 +105,17 @@ pub struct Cli {
     /// How quickly to forget the most recent change.
     #[arg(long, default_value_t = FORGET_AFTER, value_name = "INT")]
     pub forget_after: usize,
+    /// The cost for each lever flipped, with --lever.
+    #[arg(long, default_value_t = LEVER_COST, value_name = "INT")]
+    pub lever_cost: usize,
+    /// Milliseconds a foot needs between two of its changes, checked only
+    /// in music with a \tempo marking.
+    #[arg(long, default_value_t = MIN_CHANGE_TIME, value_name = "MS")]
+    pub min_change_time: usize,
     /// How much to penalize successive changes.
     #[arg(long, default_value_t = QUICK_CHANGE_COST, value_name = "INT")]
     pub quick_change_cost: usize,
-    /// How much quick-change-cost decays each beat without a change.
+    /// How much quick-change-cost decays each quarter note without a change.
     #[arg(long, default_value_t = QUICK_CHANGE_DECAY, value_name = "INT")]
     pub quick_change_decay: usize,
     /// The cost for each pedal change.
@@ -53,8 +124,29 @@ pub struct Cli {
     /// How much to penalize distance between pedals for successive changes.
     #[arg(long, default_value_t = PEDAL_DISTANCE_COST, value_name = "INT")]
     pub pedal_diatance_cost: usize,
+    /// Extra milliseconds a foot needs for each pedal it moves across.
+    #[arg(long, default_value_t = PEDAL_MOVE_TIME, value_name = "MS")]
+    pub pedal_move_time: usize,
 }
 
-lazy_static! {
-    pub static ref CONST: Cli = Cli::parse();
+impl Cli {
+    // The solver only sees the weights, never the rest of the flags.
+    pub fn solver_config(&self) -> SolverConfig {
+        SolverConfig {
+            buzz_cost: self.buzz_cost,
+            cross_string_cost: self.cross_string_cost,
+            decay: self.decay,
+            double_string_cost: self.double_string_cost,
+            early_change_cost: self.early_change_cost,
+            forget_after: self.forget_after,
+            let_ring: self.let_ring,
+            lever_cost: self.lever_cost,
+            min_change_time: self.min_change_time,
+            quick_change_cost: self.quick_change_cost,
+            quick_change_decay: self.quick_change_decay,
+            pedal_cost: self.pedal_cost,
+            pedal_distance_cost: self.pedal_diatance_cost,
+            pedal_move_time: self.pedal_move_time,
+        }
+    }
 }
@@ -0,0 +1,74 @@
+// Default weights, shared by the library and the command-line flags.
+pub const BUZZ_COST: usize = 2000;
+pub const CROSS_STRING_COST: usize = 1200;
+pub const DECAY: usize = 1;
+pub const DOUBLE_STRING_COST: usize = 100;
+pub const EARLY_CHANGE_COST: usize = 300;
+pub const FORGET_AFTER: usize = 4;
+pub const LEVER_COST: usize = 1000;
+pub const MIN_CHANGE_TIME: usize = 200;
+pub const QUICK_CHANGE_COST: usize = 30;
+pub const QUICK_CHANGE_DECAY: usize = 10;
+pub const PEDAL_COST: usize = 1000;
+pub const PEDAL_DISTANCE_COST: usize = 1;
+pub const PEDAL_MOVE_TIME: usize = 50;
+
+// Everything the solver needs to know that isn't the music itself.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+pub struct SolverConfig {
+    // How much to penalize changing a pedal while its strings ring.
+    pub buzz_cost: usize,
+    // How much to penalize crossed strings (eg E# and Fb).
+    pub cross_string_cost: usize,
+    // How many quarter notes a string rings past its written length.
+    pub decay: usize,
+    // How much to penalize doubled strings (eg E# and F).
+    pub double_string_cost: usize,
+    // How much to penalize pedaling early.
+    pub early_change_cost: usize,
+    // How many beats until the most recent change is forgotten.
+    pub forget_after: usize,
+    // Whether strings ring until damped, rather than for a while.
+    pub let_ring: bool,
+    // The cost for each lever flipped, on a lever harp.
+    pub lever_cost: usize,
+    // How many milliseconds a foot needs between two of its changes.
+    pub min_change_time: usize,
+    // How much to penalize successive changes.
+    pub quick_change_cost: usize,
+    // How much quick_change_cost decays each quarter note without a change.
+    pub quick_change_decay: usize,
+    // The cost for each pedal change.
+    pub pedal_cost: usize,
+    // How much to penalize distance between pedals for successive changes.
+    pub pedal_distance_cost: usize,
+    // How many more milliseconds it needs for each pedal it moves across.
+    pub pedal_move_time: usize,
+}
+
+impl SolverConfig {
+    pub fn new() -> SolverConfig {
+        SolverConfig {
+            buzz_cost: BUZZ_COST,
+            cross_string_cost: CROSS_STRING_COST,
+            decay: DECAY,
+            double_string_cost: DOUBLE_STRING_COST,
+            early_change_cost: EARLY_CHANGE_COST,
+            forget_after: FORGET_AFTER,
+            let_ring: false,
+            lever_cost: LEVER_COST,
+            min_change_time: MIN_CHANGE_TIME,
+            quick_change_cost: QUICK_CHANGE_COST,
+            quick_change_decay: QUICK_CHANGE_DECAY,
+            pedal_cost: PEDAL_COST,
+            pedal_distance_cost: PEDAL_DISTANCE_COST,
+            pedal_move_time: PEDAL_MOVE_TIME,
+        }
+    }
+}
+
+impl Default for SolverConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,66 +1,335 @@
-use crate::astar::{AstarState, Change};
-use crate::cli::CONST;
+use crate::astar::{step, AstarState, Beat, Change};
+use crate::config::SolverConfig;
 use crate::prelude::*;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt;
 
-pub fn astar_cost(state: AstarState, target: AstarState) -> usize {
+// The beat is None after the last one.
+pub fn astar_cost(
+    state: AstarState,
+    target: AstarState,
+    beat: Option<&Beat>,
+    config: &SolverConfig,
+) -> usize {
     let mut out = 0;
-    out += pedal_cost(state.last_left, target.last_left);
-    out += pedal_cost(state.last_right, target.last_right);
-    out += num_same(target.pedals) * CONST.double_string_cost;
-    out += num_crossed(target.pedals) * CONST.cross_string_cost;
-    out += quick_change_cost(state.last_left, target.last_left);
-    out += quick_change_cost(state.last_right, target.last_right);
-    out += early_change_cost(target);
+    out += pedal_cost(state.last_left, target.last_left, config);
+    out += pedal_cost(state.last_right, target.last_right, config);
+    let pedals = target.harp();
+    out += doubled_in_the_way(pedals, beat).len() * config.double_string_cost;
+    out += in_the_way(crossed_pairs(pedals), pedals, beat).len()
+        * config.cross_string_cost;
+    out += quick_change_cost(
+        state.last_left,
+        target.last_left,
+        target.elapsed as usize,
+        config,
+    );
+    out += quick_change_cost(
+        state.last_right,
+        target.last_right,
+        target.elapsed as usize,
+        config,
+    );
+    out += early_change_cost(target, config);
+    out += buzz_cost(target, config);
     out
 }
 
-pub fn astar_heuristic(state: AstarState, target: Harp) -> usize {
+pub fn astar_heuristic(
+    state: AstarState,
+    target: Harp,
+    config: &SolverConfig,
+) -> usize {
+    let target = PackedHarp::from(target);
     let mut out = 0;
-    out += CONST.pedal_cost * num_changes(state.pedals, target, 0..=6);
-    out += CONST.double_string_cost * num_same(target);
-    out += CONST.cross_string_cost * num_crossed(target);
+    out += config.pedal_cost * state.pedals.num_changes(target, ALL_PEDALS);
+    out += config.double_string_cost * target.num_same();
+    out += config.cross_string_cost * target.num_crossed();
     out
 }
 
-fn pedal_diff(old: Note, new: Note) -> usize {
+// Doubled or crossed strings only get in the way when both are played
+// within an octave of each other. Without octaves we can't tell.
+fn in_the_way(
+    pairs: Vec<(usize, usize)>,
+    pedals: Harp,
+    beat: Option<&Beat>,
+) -> Vec<(usize, usize)> {
+    let Some(register) = beat.and_then(|b| b.register.as_ref()) else {
+        return pairs;
+    };
+    let strings = register.strings(pedals);
+    pairs
+        .into_iter()
+        .filter(|(i, j)| {
+            strings[*i]
+                .iter()
+                .cartesian_product(&strings[*j])
+                .any(|(a, b)| a.abs_diff(*b) < 7)
+        })
+        .collect_vec()
+}
+
+// A glissando's or bisbigliando's doubled strings are what was asked for,
+// though not strings crossed out of order.
+fn doubled_in_the_way(
+    pedals: Harp,
+    beat: Option<&Beat>,
+) -> Vec<(usize, usize)> {
+    if beat.is_some_and(|b| b.gliss) {
+        return vec![];
+    }
+    let bisb = beat.map(|b| &b.bisb[..]).unwrap_or_default();
+    let wanted = |i: usize| {
+        idx_to_note(i, pedals[i]).is_some_and(|n| bisb.contains(&note_to_pc(n)))
+    };
+    let pairs = doubled_pairs(pedals)
+        .into_iter()
+        .filter(|(i, j)| !(wanted(*i) && wanted(*j)))
+        .collect_vec();
+    in_the_way(pairs, pedals, beat)
+}
+
+pub(crate) fn pedal_diff(old: Note, new: Note) -> usize {
     let f = |n: Note| name_to_usize(n.name);
     f(old).saturating_sub(f(new)) + f(new).saturating_sub(f(old))
 }
 
-pub fn quick_change_cost(old: Option<Change>, new: Option<Change>) -> usize {
+// elapsed is the time since the previous beat, old.time doesn't include it.
+pub fn quick_change_cost(
+    old: Option<Change>,
+    new: Option<Change>,
+    elapsed: usize,
+    config: &SolverConfig,
+) -> usize {
     let mut out = 0;
-    // cost is decayed quick_change_cost
+    // cost is decayed quick_change_cost, counting from a quarter note
     if let Some(old) = old {
         if let Some(new) = new {
             if old.note != new.note {
-                out += CONST
+                let gap = (old.time as usize + elapsed).saturating_sub(QUARTER);
+                out += config
                     .quick_change_cost
-                    .saturating_sub(CONST.quick_change_decay * old.time);
+                    .saturating_sub(config.quick_change_decay * gap / QUARTER);
             }
         }
     }
     out
 }
 
-pub fn early_change_cost(state: AstarState) -> usize {
+// Scales with how long the pedals have been early.
+pub fn early_change_cost(state: AstarState, config: &SolverConfig) -> usize {
+    let each = config.early_change_cost * state.elapsed as usize / QUARTER;
+    state.early.count_ones() as usize * each
+}
+
+// Changing a pedal while its strings ring makes them buzz or bend.
+pub fn buzz_cost(state: AstarState, config: &SolverConfig) -> usize {
+    state.buzz.count_ones() as usize * config.buzz_cost
+}
+
+pub fn pedal_cost(
+    old: Option<Change>,
+    new: Option<Change>,
+    config: &SolverConfig,
+) -> usize {
     let mut out = 0;
-    for b in state.early {
-        if b {
-            out += CONST.early_change_cost;
+    if let Some(new) = new {
+        if let Some(old) = old {
+            out += config.pedal_distance_cost * pedal_diff(old.note, new.note);
+        }
+        if new.time == 0 {
+            out += config.pedal_cost;
         }
     }
     out
 }
 
-pub fn pedal_cost(old: Option<Change>, new: Option<Change>) -> usize {
-    let mut out = 0;
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum CostKind {
+    PedalChange,
+    PedalDistance,
+    QuickChange,
+    EarlyChange,
+    DoubledString,
+    CrossedString,
+    Buzz,
+}
+
+impl fmt::Display for CostKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CostKind::PedalChange => write!(f, "pedal change"),
+            CostKind::PedalDistance => write!(f, "distance from last change"),
+            CostKind::QuickChange => write!(f, "quick change"),
+            CostKind::EarlyChange => write!(f, "early change"),
+            CostKind::DoubledString => write!(f, "doubled string"),
+            CostKind::CrossedString => write!(f, "crossed string"),
+            CostKind::Buzz => write!(f, "buzz, string still ringing"),
+        }
+    }
+}
+
+// One term of astar_cost, attributed to where it was incurred.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CostItem {
+    // Index into the music, or its length for changes after the last beat.
+    pub beat: usize,
+    pub foot: Option<Foot>,
+    pub kind: CostKind,
+    pub notes: Vec<Note>,
+    pub cost: usize,
+}
+
+pub type Ledger = Vec<CostItem>;
+
+pub fn ledger_total(ledger: &Ledger) -> usize {
+    ledger.iter().map(|i| i.cost).sum()
+}
+
+fn foot_items(
+    old: Option<Change>,
+    new: Option<Change>,
+    elapsed: usize,
+    config: &SolverConfig,
+) -> Vec<(CostKind, Note, usize)> {
+    let mut out = vec![];
     if let Some(new) = new {
+        if new.time == 0 {
+            out.push((CostKind::PedalChange, new.note, config.pedal_cost));
+        }
         if let Some(old) = old {
-            out += CONST.pedal_diatance_cost * pedal_diff(old.note, new.note);
+            let distance =
+                config.pedal_distance_cost * pedal_diff(old.note, new.note);
+            out.push((CostKind::PedalDistance, new.note, distance));
         }
-        if new.time == 0 {
-            out += CONST.pedal_cost;
+        let quick = quick_change_cost(old, Some(new), elapsed, config);
+        out.push((CostKind::QuickChange, new.note, quick));
+    }
+    out
+}
+
+// The same terms as astar_cost, itemised. Zero terms are left out.
+pub fn cost_items(
+    state: AstarState,
+    target: AstarState,
+    chord: Option<&Beat>,
+    config: &SolverConfig,
+) -> Ledger {
+    let beat = target.beat - 1;
+    let mut out = vec![];
+    let feet = [
+        (Foot::Left, state.last_left, target.last_left),
+        (Foot::Right, state.last_right, target.last_right),
+    ];
+    for (foot, old, new) in feet {
+        for (kind, note, cost) in
+            foot_items(old, new, target.elapsed as usize, config)
+        {
+            out.push(CostItem {
+                beat,
+                foot: Some(foot),
+                kind,
+                notes: vec![note],
+                cost,
+            });
+        }
+    }
+    let pedals = target.harp();
+    let kinds = [
+        (
+            CostKind::DoubledString,
+            doubled_in_the_way(pedals, chord),
+            config.double_string_cost,
+        ),
+        (
+            CostKind::CrossedString,
+            in_the_way(crossed_pairs(pedals), pedals, chord),
+            config.cross_string_cost,
+        ),
+    ];
+    for (kind, pairs, cost) in kinds {
+        for (i, j) in pairs {
+            out.push(CostItem {
+                beat,
+                foot: None,
+                kind,
+                notes: [i, j]
+                    .iter()
+                    .filter_map(|k| idx_to_note(*k, pedals[*k]))
+                    .collect(),
+                cost,
+            });
+        }
+    }
+    for (i, a) in pedals.iter().enumerate() {
+        if let (true, Some(note)) = (target.is_early(i), idx_to_note(i, *a)) {
+            out.push(CostItem {
+                beat,
+                foot: Some(note.foot()),
+                kind: CostKind::EarlyChange,
+                notes: vec![note],
+                cost: config.early_change_cost * target.elapsed as usize
+                    / QUARTER,
+            });
+        }
+    }
+    for (i, a) in pedals.iter().enumerate() {
+        if let (true, Some(note)) = (target.is_buzzing(i), idx_to_note(i, *a)) {
+            out.push(CostItem {
+                beat,
+                foot: Some(note.foot()),
+                kind: CostKind::Buzz,
+                notes: vec![note],
+                cost: config.buzz_cost,
+            });
+        }
+    }
+    out.retain(|i| i.cost > 0);
+    out
+}
+
+// Replay a finished plan, itemising what each beat costs.
+// Targets are the notes actually played, then the final setting,
+// and pedals are the changes made at each of them.
+pub fn plan_ledger(
+    diagram: Harp,
+    targets: &[Harp],
+    mid: &[Beat],
+    pedals: &Pedals,
+    config: &SolverConfig,
+) -> Ledger {
+    let mut state = AstarState::new(diagram);
+    let mut out = vec![];
+    for (i, (target, changes)) in targets.iter().zip(pedals).enumerate() {
+        let left = changes.iter().find(|n| n.is_left()).copied();
+        let right = changes.iter().find(|n| n.is_right()).copied();
+        let mut next = state;
+        next.advance(
+            left,
+            right,
+            *target,
+            config.forget_after,
+            step(mid, i, config),
+        );
+        let mut items = cost_items(state, next, mid.get(i), config);
+        // A plan may ask a foot for more than one change at once.
+        for extra in changes
+            .iter()
+            .filter(|n| Some(**n) != left && Some(**n) != right)
+        {
+            next.pedals.set(*extra);
+            items.push(CostItem {
+                beat: next.beat - 1,
+                foot: Some(extra.foot()),
+                kind: CostKind::PedalChange,
+                notes: vec![*extra],
+                cost: config.pedal_cost,
+            });
         }
+        out.append(&mut items);
+        state = next;
     }
     out
 }
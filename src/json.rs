@@ -0,0 +1,168 @@
+use crate::cost::Ledger;
+use crate::lever::LeverPlan;
+use crate::parse::{to_parsed, Measure, NoteRequest, Parsed};
+use crate::prelude::*;
+use crate::verify::Verified;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+
+// The music as a program would send it, the same as a .hrp file:
+//
+// {
+//   "start": ["Flat", null, "Natural", null, null, "Sharp", null],
+//   "measures": [[
+//     {"notes": [{"This": {"note": "Eb", "octave": 4}}], "duration": 256},
+//     {"notes": [{"Any": {"note": "F#", "octave": null}}, "Rest"]}
+//   ]],
+//   "goal": null
+// }
+//
+// Diagrams are in pedal order, D C B | E F G A, null where unset.
+// Beats may also give "sustain" in ticks, a "tempo" of {"unit", "bpm"},
+// "directives" of {"changes": ["F#"], "hold_left", "hold_right"},
+// a "checkpoint" diagram, and "gliss": true. Notes may also be "Damp"
+// or {"Bisb": pitch}. Durations are in ticks, 256 to the quarter.
+//
+// Output is a list of sections, each {"title", "plans"}, and each plan
+// is a JsonPlan, with the changes before each beat split by foot and
+// the last entry the changes for the goal. With --lever, each section
+// instead has a "plan" of {"setup", "flips", "cost"}.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonInput {
+    pub start: Option<Harp>,
+    pub measures: Vec<Measure>,
+    pub goal: Option<Harp>,
+}
+
+// The parser checks these as it goes, but JSON skips the parser.
+fn check(input: &JsonInput) -> Result<(), String> {
+    for (m, measure) in input.measures.iter().enumerate() {
+        for (b, beat) in measure.iter().enumerate() {
+            let place = format!("measure {}, beat {}", m + 1, b + 1);
+            for request in &beat.notes {
+                let pitch = match request {
+                    NoteRequest::This(p)
+                    | NoteRequest::Any(p)
+                    | NoteRequest::Bisb(p) => p,
+                    _ => continue,
+                };
+                if !pitch.on_harp() {
+                    return Err(format!("{pitch} in {place} is off the harp"));
+                }
+                let strings = pc_to_notes(note_to_pc(pitch.note)).len();
+                if matches!(request, NoteRequest::Bisb(_)) && strings < 2 {
+                    return Err(format!(
+                        "{pitch} in {place} is only on one string, \
+                         so can't be a bisbigliando"
+                    ));
+                }
+            }
+            if !beat.directives.is_possible() {
+                return Err(format!(
+                    "The directives in {place} ask for more than one \
+                     change per foot, or a change for a held foot"
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_json(s: &str) -> Result<Parsed, String> {
+    let input: JsonInput =
+        serde_json::from_str(s).map_err(|e| e.to_string())?;
+    check(&input)?;
+    Ok(to_parsed(input.start, input.measures, input.goal))
+}
+
+// One pedaling, with its changes split by foot.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonPlan {
+    pub diagram: Harp,
+    pub destination: Harp,
+    pub spelling: Vec<Harp>,
+    pub left: Pedals,
+    pub right: Pedals,
+    pub cost: usize,
+    pub breakdown: Ledger,
+}
+
+impl From<&Candidate> for JsonPlan {
+    fn from(c: &Candidate) -> JsonPlan {
+        let (left, right) = unzip_pedals(&c.pedals);
+        JsonPlan {
+            diagram: c.diagram,
+            destination: c.destination,
+            spelling: c.spelling.clone(),
+            left,
+            right,
+            cost: c.cost,
+            breakdown: c.ledger.clone(),
+        }
+    }
+}
+
+// The pedalings found for one section, best first.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonSection {
+    pub title: Option<String>,
+    pub plans: Vec<JsonPlan>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonLeverSection {
+    pub title: Option<String>,
+    pub plan: LeverPlan,
+}
+
+// A plan as --format json writes it, to be checked with --verify.
+// Only the first plan of each section is read.
+#[derive(Clone, Debug, Deserialize)]
+struct JsonPlanInput {
+    diagram: Harp,
+    left: Pedals,
+    right: Pedals,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct JsonSectionInput {
+    plans: Vec<JsonPlanInput>,
+}
+
+// The diagram and the changes before each beat, for each section.
+pub fn parse_json_plans(s: &str) -> Result<Vec<(Harp, Pedals)>, String> {
+    let sections: Vec<JsonSectionInput> =
+        serde_json::from_str(s).map_err(|e| e.to_string())?;
+    sections
+        .into_iter()
+        .enumerate()
+        .map(|(i, section)| {
+            let plan = section
+                .plans
+                .into_iter()
+                .next()
+                .ok_or_else(|| format!("Section {} has no plans", i + 1))?;
+            let pedals = plan
+                .left
+                .into_iter()
+                .zip_longest(plan.right)
+                .map(|x| {
+                    x.reduce(|mut l, mut r| {
+                        l.append(&mut r);
+                        l
+                    })
+                })
+                .collect_vec();
+            Ok((plan.diagram, pedals))
+        })
+        .collect()
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct JsonVerifiedSection {
+    pub title: Option<String>,
+    pub verified: Verified,
+    // The total of the solver's own best, if it finds one.
+    pub best: Option<usize>,
+}
@@ -0,0 +1,273 @@
+use crate::assign::Conflict;
+use crate::config::SolverConfig;
+use crate::prelude::*;
+use crate::solve::get_chords;
+use itertools::Itertools;
+use pathfinding::directed::astar::astar;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// A lever harp has a lever on every string, raising it a semitone.
+// Each string name is tuned flat or natural, with the lever down, and
+// its lever gives the accidental above. Eb major is the usual tuning.
+pub type Tuning = [Accidental; 7];
+
+// In pedal order, D C B | E F G A, as in a pedal diagram.
+pub const EB_TUNING: Tuning =
+    [Natural, Natural, Flat, Flat, Natural, Natural, Flat];
+
+// Levers are set by hand, the left hand taking the strings below middle C.
+const RIGHT_HAND_FROM: usize = 28;
+
+const DEGREES: [Name; 7] = [
+    Name::C,
+    Name::D,
+    Name::E,
+    Name::F,
+    Name::G,
+    Name::A,
+    Name::B,
+];
+
+// Reads a diagram such as "--^|^--^", with ^ for flat-tuned strings
+// and - for natural-tuned ones.
+pub fn parse_tuning(s: &str) -> Result<Tuning, String> {
+    let settings = s.chars().filter(|c| *c != '|').collect_vec();
+    if settings.len() != 7 || s.chars().nth(3) != Some('|') {
+        return Err("expected a diagram such as --^|^--^".to_string());
+    }
+    let mut out = [Natural; 7];
+    for (i, c) in settings.into_iter().enumerate() {
+        out[i] = match c {
+            '^' => Flat,
+            '-' => Natural,
+            _ => return Err(format!("'{c}' is not ^ or -")),
+        };
+    }
+    Ok(out)
+}
+
+fn raised(accidental: Accidental) -> Option<Accidental> {
+    match accidental {
+        Flat => Some(Natural),
+        Natural => Some(Sharp),
+        Sharp => None,
+    }
+}
+
+// Whether the note needs its lever up, if the string can play it at all.
+fn lever_up(tuning: Tuning, note: Note) -> Option<bool> {
+    let low = tuning[name_to_usize(note.name)];
+    if note.accidental == low {
+        Some(false)
+    } else if Some(note.accidental) == raised(low) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn string_pitch(tuning: Tuning, string: usize, up: bool) -> Pitch {
+    let name = DEGREES[string % 7];
+    let low = tuning[name_to_usize(name)];
+    let accidental = if up { raised(low).unwrap_or(low) } else { low };
+    Pitch::new(Note { name, accidental }, Some((string / 7) as u8))
+}
+
+fn hand_mask(string: usize) -> u64 {
+    if string < RIGHT_HAND_FROM {
+        (1 << RIGHT_HAND_FROM) - 1
+    } else {
+        !((1 << RIGHT_HAND_FROM) - 1)
+    }
+}
+
+// Each spelling of a beat, as the levers it needs: (strings, which are up).
+type LeverTarget = (u64, u64);
+
+fn lever_targets(
+    tuning: Tuning,
+    targets: &[Harp],
+    fixed: &[Pitch],
+    free: &[Pitch],
+) -> Vec<LeverTarget> {
+    targets
+        .iter()
+        .filter_map(|t| {
+            let mut strings = 0;
+            let mut up = 0;
+            for p in spell_pitches(*t, fixed, free) {
+                let bit = 1 << p.string()?;
+                strings |= bit;
+                if lever_up(tuning, p.note)? {
+                    up |= bit;
+                }
+            }
+            Some((strings, up))
+        })
+        .unique()
+        .collect_vec()
+}
+
+// Strings are bits, numbered as in Pitch::string.
+// A string is busy from when it is played until its hand next rests,
+// and only strings that have never been played can be set for free.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+struct LeverState {
+    beat: usize,
+    up: u64,
+    busy: u64,
+    seen: u64,
+}
+
+// The strings of both hands, if either plays any of them.
+fn hands_playing(strings: u64) -> u64 {
+    (0..64)
+        .filter(|s| strings & (1 << s) != 0)
+        .fold(0, |m, s| m | hand_mask(s))
+}
+
+// Playing a spelling of the beat, if no lever it needs is busy,
+// with how many levers were flipped for it.
+fn lever_step(
+    state: LeverState,
+    (strings, up): LeverTarget,
+) -> Option<(LeverState, usize)> {
+    let flips = (state.up ^ up) & strings;
+    if flips & state.busy != 0 {
+        return None;
+    }
+    // A hand with nothing to play here is free to reach its levers.
+    let next = LeverState {
+        beat: state.beat + 1,
+        up: (state.up & !strings) | up,
+        busy: (state.busy & hands_playing(strings)) | strings,
+        seen: state.seen | strings,
+    };
+    Some((next, (flips & state.seen).count_ones() as usize))
+}
+
+fn lever_succ(
+    state: LeverState,
+    beats: &[Vec<LeverTarget>],
+    config: &SolverConfig,
+) -> Vec<(LeverState, usize)> {
+    let Some(targets) = beats.get(state.beat) else {
+        return vec![];
+    };
+    targets
+        .iter()
+        .filter_map(|t| lever_step(state, *t))
+        .map(|(next, flips)| (next, flips * config.lever_cost))
+        .collect_vec()
+}
+
+// The levers to set before playing, and those flipped at each beat,
+// which is always a rest for the hand that flips them.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct LeverPlan {
+    pub setup: Vec<Pitch>,
+    pub flips: Vec<Vec<Pitch>>,
+    pub cost: usize,
+}
+
+impl fmt::Display for LeverPlan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Levers up: [{}]", self.setup.iter().join(", "))?;
+        write!(
+            f,
+            "\n[{}]",
+            self.flips
+                .iter()
+                .map(|v| format!("[{}]", v.iter().join(", ")))
+                .join(", ")
+        )
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LeverError {
+    Conflicts(Vec<(usize, Conflict)>),
+    // Beats with a note missing its octave, so its string is unknown.
+    NoOctave(Vec<usize>),
+}
+
+// Which strings to flip, and when, for the fewest flips.
+// Spellings come from assign, as for the pedal harp, but the search is
+// over each string's lever, which only a resting hand can reach.
+pub fn find_lever_plan(
+    input: &MusicInput,
+    tuning: Tuning,
+    config: &SolverConfig,
+) -> Result<Option<LeverPlan>, LeverError> {
+    let chords = get_chords(input).map_err(LeverError::Conflicts)?;
+    let no_octave = input
+        .music
+        .iter()
+        .map(|beat| &beat.pitches)
+        .positions(|(fixed, free)| {
+            fixed.iter().chain(free).any(|p| p.octave.is_none())
+        })
+        .collect_vec();
+    if !no_octave.is_empty() {
+        return Err(LeverError::NoOctave(no_octave));
+    }
+    let beats = chords
+        .iter()
+        .zip(&input.music)
+        .map(|(beat, written)| {
+            let (fixed, free) = &written.pitches;
+            lever_targets(tuning, &beat.targets, fixed, free)
+        })
+        .collect_vec();
+    let start = LeverState {
+        beat: 0,
+        up: 0,
+        busy: 0,
+        seen: 0,
+    };
+    let Some((path, cost)) = astar(
+        &start,
+        |s| lever_succ(*s, &beats, config),
+        |_| 0,
+        |s| s.beat == beats.len(),
+    ) else {
+        return Ok(None);
+    };
+    let last = path.last().copied().unwrap_or(start);
+    let setup = (0..64)
+        .filter(|s| last.seen & (1 << s) != 0)
+        .filter_map(|s| {
+            let first_up = path
+                .iter()
+                .find(|st| st.seen & (1 << s) != 0)
+                .is_some_and(|st| st.up & (1 << s) != 0);
+            first_up.then(|| string_pitch(tuning, s, true))
+        })
+        .collect_vec();
+    // The hands playing at each beat, as found again from the path.
+    let playing = path
+        .iter()
+        .tuple_windows()
+        .map(|(a, b)| {
+            beats[a.beat]
+                .iter()
+                .find(|t| lever_step(*a, **t).is_some_and(|(n, _)| n == *b))
+                .map_or(0, |(strings, _)| hands_playing(*strings))
+        })
+        .collect_vec();
+    // Each flip is made in the hand's last rest before it is needed,
+    // which came after the string last sounded, or it would be busy.
+    let mut flips = vec![vec![]; path.len() - 1];
+    for (i, (a, b)) in path.iter().tuple_windows().enumerate() {
+        let flipped = (a.up ^ b.up) & a.seen;
+        for s in (0..64).filter(|s| flipped & (1 << s) != 0) {
+            let rest = (0..i)
+                .rev()
+                .find(|j| playing[*j] & (1 << s) == 0)
+                .unwrap_or(i);
+            flips[rest].push(string_pitch(tuning, s, b.up & (1 << s) != 0));
+        }
+    }
+    Ok(Some(LeverPlan { setup, flips, cost }))
+}
@@ -7,9 +7,14 @@ pub mod assign;
 pub mod astar;
 pub mod candidate;
 pub mod cli;
+pub mod config;
 pub mod cost;
+pub mod json;
+pub mod lever;
 pub mod lilypond;
+pub mod musicxml;
 pub mod parse;
 pub mod prelude;
 pub mod solve;
 pub mod util;
+pub mod verify;
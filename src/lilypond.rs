@@ -10,79 +10,159 @@ fn pedal_markup(diagram: Harp) -> String {
     out
 }
 
-fn add_measure(contents: &mut Vec<String>, measure: Vec<Vec<Note>>) {
-    let mut new_line = String::from("");
-    for beat in measure {
-        let len = beat.len();
-        if len > 1 {
-            new_line.push_str("<< ");
+fn gliss_markup(setting: Harp) -> String {
+    let mut out = String::with_capacity(60);
+    out.push_str("<>^\\markup { \\column { \\italic \"gliss.\" ");
+    out.push_str("\\harp-pedal \"");
+    out.push_str(&pedal_diagram(setting));
+    out.push_str("\" } }");
+    out
+}
+
+// Durations that aren't a single note value are tied.
+fn ly_durations(ticks: usize) -> Vec<String> {
+    split_value(ticks)
+        .into_iter()
+        .map(|(base, dots)| format!("{base}{}", ".".repeat(dots)))
+        .collect_vec()
+}
+
+// The signature that counts a measure in the longest note value that
+// fits it evenly, eg 3/4, 7/8 or 5/16.
+pub fn time_signature(ticks: usize) -> (usize, usize) {
+    let mut base = 4;
+    while !ticks.is_multiple_of(4 * QUARTER / base) && base < 64 {
+        base *= 2;
+    }
+    (ticks / (4 * QUARTER / base), base)
+}
+
+fn ly_time(ticks: usize) -> String {
+    let (beats, base) = time_signature(ticks);
+    format!("\\time {beats}/{base}")
+}
+
+// Inside \fixed c', notes without marks are in octave 4.
+fn ly_octave(octave: Option<u8>) -> String {
+    match octave {
+        Some(o) if o > 4 => "'".repeat(o as usize - 4),
+        Some(o) => ",".repeat(4 - o as usize),
+        None => String::new(),
+    }
+}
+
+fn ly_chord(beat: &[Pitch]) -> String {
+    let mut out = String::from("");
+    if beat.len() > 1 {
+        out.push('<');
+    }
+    for (i, pitch) in beat.iter().enumerate() {
+        if i > 0 {
+            out.push(' ');
         }
-        for note in beat {
-            new_line.push_str(&note.name.to_string().to_lowercase());
-            match note.accidental {
-                Accidental::Flat => {
-                    new_line.push_str("es ");
-                }
-                Accidental::Natural => {
-                    new_line.push(' ');
-                }
-                Accidental::Sharp => {
-                    new_line.push_str("is ");
-                }
-            }
+        let note = pitch.note;
+        out.push_str(&note.name.to_string().to_lowercase());
+        match note.accidental {
+            Accidental::Flat => out.push_str("es"),
+            Accidental::Natural => (),
+            Accidental::Sharp => out.push_str("is"),
         }
-        if len > 1 {
-            new_line.push_str(">> ");
-        } else if len == 0 {
-            new_line.push_str("r ");
+        out.push_str(&ly_octave(pitch.octave));
+    }
+    if beat.len() > 1 {
+        out.push('>');
+    }
+    out
+}
+
+// A glissando is marked with the setting it sweeps.
+// The time signature is only given when it changes.
+fn add_measure(
+    contents: &mut Vec<String>,
+    measure: Vec<(Vec<Pitch>, usize)>,
+    glisses: &mut impl Iterator<Item = Option<Harp>>,
+    time: &mut Option<String>,
+) {
+    let mut new_line = String::from("");
+    let length = measure.iter().map(|(_, ticks)| ticks).sum::<usize>();
+    if length > 0 {
+        let this = ly_time(length);
+        if time.as_ref() != Some(&this) {
+            new_line.push_str(&this);
+            new_line.push(' ');
+            *time = Some(this);
+        }
+    }
+    for (beat, ticks) in measure {
+        if let Some(setting) = glisses.next().flatten() {
+            new_line.push_str(&gliss_markup(setting));
+            new_line.push(' ');
         }
+        let chord = if beat.is_empty() {
+            "r".to_string()
+        } else {
+            ly_chord(&beat)
+        };
+        let tie = if beat.is_empty() { " " } else { "~ " };
+        new_line.push_str(
+            &ly_durations(ticks)
+                .iter()
+                .map(|d| format!("{chord}{d}"))
+                .join(tie),
+        );
+        new_line.push(' ');
     }
-    new_line.push_str("\\bar \"|\"");
-    contents.push(new_line)
+    contents.push(new_line.trim_end().to_string())
 }
 
 fn make_ly_treble(
-    treble: Vec<Vec<Vec<Note>>>,
+    name: &str,
+    treble: Vec<Vec<(Vec<Pitch>, usize)>>,
+    glisses: Vec<Option<Harp>>,
     start: Harp,
     end: Harp,
 ) -> String {
+    let mut glisses = glisses.into_iter();
     let mut lines: Vec<String> = vec![
-        "treble = \\fixed c' {".to_string(),
+        format!("{name} = \\fixed c' {{"),
         "    \\clef \"treble\" \\key c \\major".to_string(),
-        "    \\override Staff.TimeSignature.stencil = ##f".to_string(),
-        "    \\cadenzaOn".to_string(),
         pedal_markup(start),
     ];
+    let mut time = None;
     for measure in treble {
-        add_measure(&mut lines, measure);
+        add_measure(&mut lines, measure, &mut glisses, &mut time);
     }
+    lines.push("\\bar \"|.\"".to_string());
     lines.push(pedal_markup(end));
     lines.push("}".to_string());
     lines.join("\n")
 }
 
-fn make_ly_pedals_l(changes: Vec<Vec<Note>>) -> String {
-    let mut out = "pedalsL = { ".to_string();
-    for beat in changes {
-        out.push_str("s ");
-        for change in beat {
-            out.push_str("_\"");
-            out.push_str(&change.to_string());
-            out.push_str("\" ");
-        }
-    }
-    out.push('}');
-    out
-}
-
-fn make_ly_pedals_r(changes: Vec<Vec<Note>>) -> String {
-    let mut out = "pedalsR = { ".to_string();
-    for beat in changes {
-        out.push_str("s ");
-        for change in beat {
-            out.push_str("_\"");
-            out.push_str(&change.to_string());
-            out.push_str("\" ");
+// One spacer per beat, plus an empty one for changes after the music,
+// so it doesn't run on past the last bar.
+fn make_ly_pedals(
+    name: &str,
+    changes: Vec<Vec<Note>>,
+    durations: &[usize],
+) -> String {
+    let mut out = format!("{name} = {{ ");
+    for (i, beat) in changes.into_iter().enumerate() {
+        let spacers = match durations.get(i) {
+            Some(d) => ly_durations(*d),
+            None => vec!["1*0".to_string()],
+        };
+        for (j, d) in spacers.iter().enumerate() {
+            out.push('s');
+            out.push_str(d);
+            out.push(' ');
+            if j > 0 {
+                continue;
+            }
+            for change in beat.iter() {
+                out.push_str("_\"");
+                out.push_str(&change.to_string());
+                out.push_str("\" ");
+            }
         }
     }
     out.push('}');
@@ -98,14 +178,77 @@ fn make_ly_cost(cost: usize) -> String {
     out
 }
 
-pub fn make_ly_file_(
-    treble: Vec<Vec<Vec<Note>>>,
-    start: Harp,
-    end: Harp,
-    changes: &Pedals,
-    cost: usize,
-) -> String {
-    let (lefts, rights) = unzip_pedals(changes);
+// One movement of the piece, with its pedaling.
+pub struct LySection {
+    pub title: Option<String>,
+    pub treble: Vec<Vec<(Vec<Pitch>, usize)>>,
+    // The setting of each beat that is a glissando.
+    pub glisses: Vec<Option<Harp>>,
+    pub start: Harp,
+    pub end: Harp,
+    pub changes: Pedals,
+    pub cost: usize,
+}
+
+// LilyPond variable names are letters only, so sections after the
+// first are told apart by a letter: treble, trebleB, trebleC, ...
+fn ly_suffix(i: usize) -> String {
+    let mut out = String::new();
+    let mut n = i;
+    while n > 0 {
+        out.insert(0, (b'A' + (n % 26) as u8) as char);
+        n /= 26;
+    }
+    out
+}
+
+fn make_ly_section(i: usize, section: LySection) -> Vec<String> {
+    let suffix = ly_suffix(i);
+    let treble = format!("treble{suffix}");
+    let pedals_r = format!("pedalsR{suffix}");
+    let pedals_l = format!("pedalsL{suffix}");
+    let (lefts, rights) = unzip_pedals(&section.changes);
+    let durations = section
+        .treble
+        .iter()
+        .flatten()
+        .map(|(_, d)| *d)
+        .collect_vec();
+    let mut lines = vec![
+        make_ly_treble(
+            &treble,
+            section.treble,
+            section.glisses,
+            section.start,
+            section.end,
+        ),
+        "".to_string(),
+        make_ly_pedals(&pedals_r, rights, &durations),
+        "".to_string(),
+        make_ly_pedals(&pedals_l, lefts, &durations),
+        "".to_string(),
+    ];
+    if let Some(title) = &section.title {
+        lines.push(format!(
+            "\\markup {{ \\large \\bold \"{}\" }}",
+            title.replace('"', "\\\"")
+        ));
+    }
+    lines.push(make_ly_cost(section.cost));
+    lines.push("".to_string());
+    lines.push("\\score {".to_string());
+    lines.push("    \\new Staff <<".to_string());
+    lines.push(format!("        \\new Voice \\{treble}"));
+    lines.push(format!("        \\new Dynamics \\{pedals_r}"));
+    lines.push(format!("        \\new Dynamics \\{pedals_l}"));
+    lines.push("    >>".to_string());
+    lines.push("}".to_string());
+    lines.push("".to_string());
+    lines
+}
+
+// One document, with a score for each section.
+pub fn make_ly_book(sections: Vec<LySection>) -> String {
     let mut lines: Vec<String> =
         vec!["\\version \"2.22.0\"",
         "",
@@ -121,18 +264,26 @@ pub fn make_ly_file_(
         "    }",
         "}",
         "",].iter().map(|s| s.to_string()).collect_vec();
-    lines.push(make_ly_treble(treble, start, end));
-    lines.push("".to_string());
-    lines.push(make_ly_pedals_r(rights));
-    lines.push("".to_string());
-    lines.push(make_ly_pedals_l(lefts));
-    lines.push("".to_string());
-    lines.push(make_ly_cost(cost));
-    lines.push("".to_string());
-    lines.push("\\new Staff <<".to_string());
-    lines.push("    \\new Voice \\treble".to_string());
-    lines.push("    \\new Dynamics \\pedalsR".to_string());
-    lines.push("    \\new Dynamics \\pedalsL".to_string());
-    lines.push(">>".to_string());
+    for (i, section) in sections.into_iter().enumerate() {
+        lines.append(&mut make_ly_section(i, section));
+    }
     lines.join("\n")
 }
+
+pub fn make_ly_file_(
+    treble: Vec<Vec<(Vec<Pitch>, usize)>>,
+    start: Harp,
+    end: Harp,
+    changes: &Pedals,
+    cost: usize,
+) -> String {
+    make_ly_book(vec![LySection {
+        title: None,
+        treble,
+        glisses: vec![],
+        start,
+        end,
+        changes: changes.clone(),
+        cost,
+    }])
+}
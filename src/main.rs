@@ -1,101 +1,595 @@
 // #![allow(dead_code)]
 #![warn(clippy::needless_pass_by_value)]
-use harp_pedal_solver::candidate::find_candidates;
+use clap::Parser;
+use harp_pedal_solver::candidate::{
+    find_candidates, find_k_candidates, find_windowed_candidates, Window,
+    WindowError,
+};
 use itertools::Itertools;
 use log::{debug, error, info, warn};
-// use simple_logger::SimpleLogger;
+use simple_logger::SimpleLogger;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::{Command, ExitCode};
 // use std::time::Instant;
 
-use harp_pedal_solver::cli::CONST;
-use harp_pedal_solver::lilypond::make_ly_file_;
+use harp_pedal_solver::cli::{Cli, Format};
+use harp_pedal_solver::config::SolverConfig;
+use harp_pedal_solver::cost::{ledger_total, Ledger};
+use harp_pedal_solver::json::{
+    parse_json, parse_json_plans, JsonLeverSection, JsonPlan, JsonSection,
+    JsonVerifiedSection,
+};
+use harp_pedal_solver::lever::{find_lever_plan, LeverError, Tuning};
+use harp_pedal_solver::lilypond::{make_ly_book, LySection};
+use harp_pedal_solver::musicxml::{make_xml_file, parse_musicxml};
 use harp_pedal_solver::parse::*;
 use harp_pedal_solver::prelude::*;
+use harp_pedal_solver::util::measure_and_beat;
+use harp_pedal_solver::verify::{verify_plan, without_written_changes};
 
 // Currently silently sets impossible measure to ~~~|~~~~
 fn main() -> ExitCode {
-    let input = fs::read_to_string(&CONST.file).expect("Unable to read file");
-    // let _show = match CONST.show {
-    //     0 => usize::MAX,
-    //     x => x,
-    // };
-    let log_level = CONST.verbose.log_level_filter();
-    // SimpleLogger::new()
-        // .with_level(log_level)
-        // .without_timestamps()
-        // .init()
-        // .unwrap();
-    let output = CONST
+    let cli = Cli::parse();
+    let input = fs::read_to_string(&cli.file).expect("Unable to read file");
+    let log_level = cli.verbose.log_level_filter();
+    SimpleLogger::new()
+        .with_level(log_level)
+        .without_timestamps()
+        .init()
+        .unwrap();
+    let output = cli
         .output
         .clone()
         .unwrap_or_else(|| PathBuf::from("pedals"));
-    let parsed = match parse(&input) {
-        Ok(x) => x,
-        Err(x) => {
-            error!("Error parsing file:\n{x}");
-            return ExitCode::FAILURE;
+    let extension = cli.file.extension().and_then(|e| e.to_str());
+    let is_xml = matches!(extension, Some("musicxml" | "xml"));
+    let sections = if extension == Some("json") {
+        match parse_json(&input) {
+            Ok(x) => vec![Section {
+                title: None,
+                parsed: x,
+            }],
+            Err(x) => {
+                error!("Error parsing file:\n{x}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else if is_xml {
+        match parse_musicxml(&input, cli.keep_spelling) {
+            Ok(x) => vec![Section {
+                title: None,
+                parsed: x,
+            }],
+            Err(x) => {
+                error!("Error parsing file:\n{x}");
+                return ExitCode::FAILURE;
+            }
+        }
+    } else {
+        match parse_sections(&input) {
+            Ok(x) => x,
+            Err(x) => {
+                error!(
+                    "Error parsing {}:\n{}",
+                    cli.file.to_string_lossy(),
+                    x.annotate(&input)
+                );
+                return ExitCode::FAILURE;
+            }
         }
     };
 
-    debug!(
-        "Starting setting: {}",
-        pedal_diagram(parsed.start.unwrap_or([None; 7]))
-    );
-    debug!("Music: {:?}", parsed.this_any);
-    debug!(
-        "Final setting: {}",
-        pedal_diagram(parsed.end.unwrap_or([None; 7]))
-    );
-
-    let mut measures = Vec::with_capacity(parsed.this_any.len());
-    let measure_lengths = parsed.this_any.clone().into_iter().map(|v| v.len());
-
-    let music_input = MusicInput {
-        diagram: parsed.start.unwrap_or([None; 7]),
-        music: parsed.this_any.into_iter().flatten().collect_vec(),
-        goal: parsed.end.unwrap_or([None; 7]),
+    let config = cli.solver_config();
+    if let Some(tuning) = cli.lever {
+        return solve_levers(&sections, tuning, &config, cli.format);
+    }
+    if let Some(plan) = &cli.verify {
+        return verify(&cli, &sections, plan.as_deref(), &config);
+    }
+    if cli.window.is_some_and(|w| w <= cli.overlap) {
+        error!("The --window must be longer than its --overlap");
+        return ExitCode::FAILURE;
+    }
+    let mut solved = Vec::with_capacity(sections.len());
+    let mut previous: Option<Harp> = None;
+    for (i, section) in sections.iter().enumerate() {
+        let name = match &section.title {
+            Some(t) => format!("\"{t}\""),
+            None => format!("section {}", i + 1),
+        };
+        let parsed = &section.parsed;
+        debug!(
+            "Starting setting: {}",
+            pedal_diagram(parsed.start.unwrap_or([None; 7]))
+        );
+        debug!(
+            "Music: {:?}",
+            parsed
+                .music
+                .iter()
+                .map(|m| m.iter().map(|b| &b.this_any).collect_vec())
+                .collect_vec()
+        );
+        debug!(
+            "Final setting: {}",
+            pedal_diagram(parsed.end.unwrap_or([None; 7]))
+        );
+
+        let mut music_input = MusicInput::from(parsed);
+        if let (true, Some(end)) = (cli.chain, previous) {
+            match meet(music_input.diagram, end) {
+                Some(h) => music_input.diagram = h,
+                None => {
+                    error!(
+                        "The starting setting of {name} doesn't match \
+                         the end of the section before it, {}",
+                        pedal_diagram(end)
+                    );
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        let measure_lengths =
+            parsed.music.iter().map(|v| v.len()).collect_vec();
+        if sections.len() > 1 {
+            info!("Solving {name}...");
+        }
+        let Some(candidates) =
+            solve(&cli, &music_input, &config, &measure_lengths)
+        else {
+            return ExitCode::FAILURE;
+        };
+        previous = Some(candidates[0].destination);
+        // Only worth the time when asked what the windows cost.
+        let global = match (cli.window, cli.explain) {
+            (Some(_), true) => find_candidates(&music_input, &config)
+                .ok()
+                .and_then(|c| c.first().map(|c| ledger_total(&c.ledger))),
+            _ => None,
+        };
+        solved.push(Solved {
+            title: section.title.clone(),
+            input: music_input,
+            measure_lengths,
+            candidates,
+            global,
+        });
+    }
+
+    if let Some(path) = &cli.annotate {
+        if extension == Some("json") || is_xml {
+            error!("Only .hrp files can be annotated");
+            return ExitCode::FAILURE;
+        }
+        let plans = solved.iter().map(|s| &s.candidates[0]).collect_vec();
+        let annotated = annotate(&input, &plans).expect("parsed before");
+        let path = path.as_ref().unwrap_or(&cli.file);
+        if fs::write(path, annotated).is_err() {
+            error!("Unable to write {}", path.to_string_lossy());
+            return ExitCode::FAILURE;
+        }
+    }
+    let show = solved
+        .iter()
+        .map(|s| s.candidates.len())
+        .fold(cli.show, usize::min);
+    let mut status = ExitCode::SUCCESS;
+    for i in 0..show {
+        let output = if i == 0 {
+            output.clone()
+        } else {
+            let mut name = output.clone().into_os_string();
+            name.push(format!("-{}", i + 1));
+            PathBuf::from(name)
+        };
+        if show > 1 && !(cli.pdf || cli.xml) && cli.format == Format::Text {
+            let cost: usize = solved.iter().map(|s| s.candidates[i].cost).sum();
+            print!("Option {} (difficulty {cost}): ", i + 1);
+        }
+        if write_output(&cli, &solved, i, &output) == ExitCode::FAILURE {
+            status = ExitCode::FAILURE;
+        }
+    }
+    if cli.format == Format::Json {
+        let json = solved
+            .iter()
+            .map(|s| JsonSection {
+                title: s.title.clone(),
+                plans: s.candidates[..show]
+                    .iter()
+                    .map(JsonPlan::from)
+                    .collect(),
+            })
+            .collect_vec();
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+    }
+    status
+}
+
+// A section of the input and the pedalings found for it.
+struct Solved {
+    title: Option<String>,
+    input: MusicInput,
+    measure_lengths: Vec<usize>,
+    candidates: Vec<Candidate>,
+    // With --window, the best total planning the whole section at once.
+    global: Option<usize>,
+}
+
+// Lever harps have no pedals, so no diagrams and no pedal output,
+// just which levers to set and when to flip them.
+fn solve_levers(
+    sections: &[Section],
+    tuning: Tuning,
+    config: &SolverConfig,
+    format: Format,
+) -> ExitCode {
+    let mut json = Vec::with_capacity(sections.len());
+    for (i, section) in sections.iter().enumerate() {
+        let parsed = &section.parsed;
+        if parsed.start.is_some()
+            || parsed.end.is_some()
+            || parsed
+                .music
+                .iter()
+                .flatten()
+                .any(|b| b.checkpoint.is_some())
+        {
+            warn!("Pedal diagrams are ignored on a lever harp");
+        }
+        let measure_lengths =
+            parsed.music.iter().map(|v| v.len()).collect_vec();
+        let place = |idx: usize| match measure_and_beat(&measure_lengths, idx) {
+            Some((m, b)) => format!("measure {m}, beat {b}"),
+            None => "the end".to_string(),
+        };
+        let input = MusicInput::from(parsed);
+        let plan = match find_lever_plan(&input, tuning, config) {
+            Ok(Some(plan)) => plan,
+            Ok(None) => {
+                error!(
+                    "Could not find any solutions. Some notes may be \
+                     out of the harp's tuning, or need a lever flipped \
+                     while that hand is playing."
+                );
+                return ExitCode::FAILURE;
+            }
+            Err(LeverError::Conflicts(x)) => {
+                for (idx, conflict) in x {
+                    error!("Impossible chord in {}: {conflict}", place(idx));
+                }
+                return ExitCode::FAILURE;
+            }
+            Err(LeverError::NoOctave(x)) => {
+                for idx in x {
+                    error!(
+                        "A note in {} needs an octave, to know its lever",
+                        place(idx)
+                    );
+                }
+                return ExitCode::FAILURE;
+            }
+        };
+        if format == Format::Json {
+            json.push(JsonLeverSection {
+                title: section.title.clone(),
+                plan,
+            });
+            continue;
+        }
+        if sections.len() > 1 {
+            match &section.title {
+                Some(t) => println!("{t}:"),
+                None => println!("Section {}:", i + 1),
+            }
+        }
+        println!("{plan}");
+    }
+    if format == Format::Json {
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+    }
+    ExitCode::SUCCESS
+}
+
+// Checks a hand-written pedaling, written in the music as changes
+// before each beat, or given as JSON, and scores it for comparison.
+fn verify(
+    cli: &Cli,
+    sections: &[Section],
+    plan: Option<&Path>,
+    config: &SolverConfig,
+) -> ExitCode {
+    let plans = match plan {
+        Some(path) => {
+            let Ok(text) = fs::read_to_string(path) else {
+                error!("Unable to read {}", path.to_string_lossy());
+                return ExitCode::FAILURE;
+            };
+            match parse_json_plans(&text) {
+                Ok(x) if x.len() == sections.len() => x,
+                Ok(x) => {
+                    error!(
+                        "The plan has {} sections, but the music has {}",
+                        x.len(),
+                        sections.len()
+                    );
+                    return ExitCode::FAILURE;
+                }
+                Err(x) => {
+                    error!("Error parsing {}:\n{x}", path.to_string_lossy());
+                    return ExitCode::FAILURE;
+                }
+            }
+        }
+        None => {
+            let mut plans = Vec::with_capacity(sections.len());
+            for section in sections {
+                let parsed = &section.parsed;
+                let Some(start) =
+                    parsed.start.filter(|s| s.iter().all(|p| p.is_some()))
+                else {
+                    error!("A pedaling needs a full starting diagram to check");
+                    return ExitCode::FAILURE;
+                };
+                let pedals = parsed
+                    .music
+                    .iter()
+                    .flatten()
+                    .map(|b| b.directives.changes.clone())
+                    .collect_vec();
+                plans.push((start, pedals));
+            }
+            plans
+        }
     };
+    let text = cli.format == Format::Text;
+    let mut json = Vec::with_capacity(sections.len());
+    let mut status = ExitCode::SUCCESS;
+    for (i, (section, (diagram, pedals))) in
+        sections.iter().zip(plans).enumerate()
+    {
+        let parsed = &section.parsed;
+        let input = MusicInput::from(parsed);
+        let measure_lengths =
+            parsed.music.iter().map(|v| v.len()).collect_vec();
+        let place = |idx: usize| match measure_and_beat(&measure_lengths, idx) {
+            Some((m, b)) => format!("measure {m}, beat {b}"),
+            None => "the end".to_string(),
+        };
+        let verified = match verify_plan(&input, diagram, &pedals, config) {
+            Ok(x) => x,
+            Err(x) => {
+                for (idx, conflict) in x {
+                    error!("Impossible chord in {}: {conflict}", place(idx));
+                }
+                return ExitCode::FAILURE;
+            }
+        };
+        if !verified.is_playable() {
+            status = ExitCode::FAILURE;
+        }
+        // Written in the music, the changes would pin the solver to them.
+        let free = match plan {
+            Some(_) => input.clone(),
+            None => without_written_changes(&input),
+        };
+        let best = find_candidates(&free, config)
+            .ok()
+            .and_then(|c| c.first().map(|c| ledger_total(&c.ledger)));
+        if !text {
+            json.push(JsonVerifiedSection {
+                title: section.title.clone(),
+                verified,
+                best,
+            });
+            continue;
+        }
+        if sections.len() > 1 {
+            match &section.title {
+                Some(t) => println!("{t}:"),
+                None => println!("Section {}:", i + 1),
+            }
+        }
+        for (idx, problem) in &verified.problems {
+            println!("In {}: {problem}", place(*idx));
+        }
+        if cli.explain {
+            print_ledger(&verified.breakdown, &measure_lengths);
+        }
+        match best {
+            Some(b) => println!(
+                "Difficulty {}, against the solver's best of {b}",
+                verified.cost
+            ),
+            None => println!("Difficulty {}", verified.cost),
+        }
+    }
+    if !text {
+        println!("{}", serde_json::to_string_pretty(&json).unwrap());
+    }
+    status
+}
 
-    let candidates = match find_candidates(&music_input) {
+// Logs why, if there are no pedalings at all.
+fn solve(
+    cli: &Cli,
+    input: &MusicInput,
+    config: &SolverConfig,
+    measure_lengths: &[usize],
+) -> Option<Vec<Candidate>> {
+    let found = if let Some(measures) = cli.window {
+        let window = Window {
+            measures,
+            overlap: cli.overlap,
+        };
+        match find_windowed_candidates(input, measure_lengths, window, config) {
+            Err(WindowError::Stuck(m)) => {
+                error!(
+                    "No way on from the pedaling planned before measure {}, \
+                     try a longer --window or --overlap",
+                    m + 1
+                );
+                return None;
+            }
+            Err(WindowError::Conflicts(x)) => Err(x),
+            Ok(x) => Ok(x),
+        }
+    } else if cli.show > 1 {
+        find_k_candidates(input, config, cli.show)
+    } else {
+        find_candidates(input, config)
+    };
+    let candidates = match found {
         Ok(x) => x,
         Err(x) => {
-            error!("Impossible chord at beats {:?}", x);
-            return ExitCode::FAILURE;
+            for (idx, conflict) in x {
+                match measure_and_beat(measure_lengths, idx) {
+                    Some((m, b)) => error!(
+                        "Impossible chord in measure {m}, beat {b}: {conflict}"
+                    ),
+                    None => error!("Impossible chord: {conflict}"),
+                }
+            }
+            return None;
         }
     };
 
     if !candidates.is_empty() {
         info!("Found {} possibilities...", candidates.len());
+        Some(candidates)
     } else {
-        error!("Could not find any solutions.");
-        return ExitCode::FAILURE;
+        error!(
+            "Could not find any solutions. \
+             The music may be too fast for the changes it needs, \
+             see --min-change-time, or its directives or pedal diagrams \
+             may be impossible."
+        );
+        None
     }
+}
 
-    let decision = &candidates[0];
+fn print_ledger(ledger: &Ledger, measure_lengths: &[usize]) {
+    println!("Cost breakdown:");
+    for item in ledger {
+        let place = match measure_and_beat(measure_lengths, item.beat) {
+            Some((m, b)) => format!("m{m} b{b}"),
+            None => "end".to_string(),
+        };
+        let foot = item.foot.map(|f| f.to_string()).unwrap_or_default();
+        let notes = item.notes.iter().join(" ");
+        println!(
+            "  {place:<9} {foot:<6} {:<26} {notes:<8} {:>6}",
+            item.kind.to_string(),
+            item.cost
+        );
+    }
+    println!("  {:<51} {:>6}", "total", ledger_total(ledger));
+}
 
-    if CONST.pdf {
-        let spell = &decision.spelling;
-        // Can also be used for pretty output.
-        let mut j = 0;
-        for n in measure_lengths {
-            let mut measure = Vec::with_capacity(n);
-            for _ in 0..n {
-                measure.push(harp_to_notes(spell[j]));
-                j += 1;
-            }
-            measures.push(measure);
+fn spelled_measures(
+    decision: &Candidate,
+    input: &MusicInput,
+    measure_lengths: &[usize],
+) -> Vec<Vec<(Vec<Pitch>, usize)>> {
+    let spell = &decision.spelling;
+    let mut measures = Vec::with_capacity(measure_lengths.len());
+    let mut j = 0;
+    for length in measure_lengths {
+        let mut measure = Vec::with_capacity(*length);
+        for beat in &input.music[j..j + length] {
+            let (fixed, free) = &beat.pitches;
+            measure.push((spell_pitches(spell[j], fixed, free), beat.duration));
+            j += 1;
         }
+        measures.push(measure);
+    }
+    measures
+}
 
-        let ly_file = make_ly_file_(
-            measures,
-            decision.diagram,
-            decision.destination,
-            &decision.pedals,
-            decision.cost,
+// Writes option i of every section.
+fn write_output(
+    cli: &Cli,
+    solved: &[Solved],
+    i: usize,
+    output: &Path,
+) -> ExitCode {
+    let log_level = cli.verbose.log_level_filter();
+    let many = solved.len() > 1;
+    let text = cli.format == Format::Text;
+    let mut ly_sections = Vec::with_capacity(solved.len());
+    for (n, section) in solved.iter().enumerate() {
+        let decision = &section.candidates[i];
+        if many && !(cli.pdf || cli.xml) && text {
+            match &section.title {
+                Some(t) => println!("{t}:"),
+                None => println!("Section {}:", n + 1),
+            }
+        }
+        if cli.explain && text {
+            print_ledger(&decision.ledger, &section.measure_lengths);
+            if let Some(best) = section.global {
+                let total = ledger_total(&decision.ledger);
+                println!(
+                    "Planned in windows, {} above the best of {best} \
+                     planned all at once",
+                    total.saturating_sub(best)
+                );
+            }
+        }
+        if !(cli.pdf || cli.xml) {
+            if text {
+                println!("{:?}", decision.pedals);
+            }
+            continue;
+        }
+        let measures = spelled_measures(
+            decision,
+            &section.input,
+            &section.measure_lengths,
         );
 
+        if cli.xml {
+            let xml_file = make_xml_file(
+                measures.clone(),
+                decision.diagram,
+                decision.destination,
+                &decision.pedals,
+            );
+            // MusicXML has no movements in one file, so each gets its own.
+            let xml_output = if many {
+                let mut name = output.to_path_buf().into_os_string();
+                name.push(format!("-section{}", n + 1));
+                PathBuf::from(name).with_extension("musicxml")
+            } else {
+                output.with_extension("musicxml")
+            };
+            if fs::write(&xml_output, xml_file).is_err() {
+                error!("Unable to write {}", xml_output.to_string_lossy());
+                return ExitCode::FAILURE;
+            }
+        }
+
+        ly_sections.push(LySection {
+            title: section.title.clone(),
+            treble: measures,
+            glisses: section
+                .input
+                .music
+                .iter()
+                .zip(&decision.spelling)
+                .map(|(beat, s)| beat.gliss.then_some(*s))
+                .collect_vec(),
+            start: decision.diagram,
+            end: decision.destination,
+            changes: decision.pedals.clone(),
+            cost: decision.cost,
+        });
+    }
+
+    if cli.pdf {
+        let ly_file = make_ly_book(ly_sections);
+
         debug!("{ly_file}");
 
         fs::write("temp.ly", ly_file).expect("./ does not exist");
@@ -116,7 +610,6 @@ fn main() -> ExitCode {
             Err(_) => ExitCode::FAILURE,
         }
     } else {
-        println!("{:?}", decision.pedals);
         ExitCode::SUCCESS
     }
 }
@@ -0,0 +1,543 @@
+use crate::lilypond::time_signature;
+use crate::parse::NoteRequest::*;
+use crate::parse::{to_parsed, BeatRequest, Measure, NoteRequest, Parsed};
+use crate::prelude::*;
+use itertools::Itertools;
+use roxmltree::{Document, Node, ParsingOptions};
+use std::collections::BTreeMap;
+
+fn child<'a, 'i>(node: Node<'a, 'i>, tag: &str) -> Option<Node<'a, 'i>> {
+    node.children().find(|c| c.has_tag_name(tag))
+}
+
+fn child_text<'a>(node: Node<'a, '_>, tag: &str) -> Option<&'a str> {
+    child(node, tag).and_then(|c| c.text()).map(|t| t.trim())
+}
+
+fn read_step(s: &str) -> Result<Name, String> {
+    match s {
+        "A" => Ok(Name::A),
+        "B" => Ok(Name::B),
+        "C" => Ok(Name::C),
+        "D" => Ok(Name::D),
+        "E" => Ok(Name::E),
+        "F" => Ok(Name::F),
+        "G" => Ok(Name::G),
+        x => Err(format!("Invalid step {x}")),
+    }
+}
+
+// Harps can't play double sharps or flats, and microtones are right out.
+fn read_alter(s: Option<&str>) -> Result<Accidental, String> {
+    match s.map(|t| t.parse::<f64>()) {
+        None => Ok(Natural),
+        Some(Ok(-1.0)) => Ok(Flat),
+        Some(Ok(0.0)) => Ok(Natural),
+        Some(Ok(1.0)) => Ok(Sharp),
+        _ => Err(format!("Unplayable alteration {}", s.unwrap())),
+    }
+}
+
+fn read_pitch(pitch: Node) -> Result<Pitch, String> {
+    let step = child_text(pitch, "step").ok_or("Pitch without a step")?;
+    let note = Note {
+        name: read_step(step)?,
+        accidental: read_alter(child_text(pitch, "alter"))?,
+    };
+    let octave = match child_text(pitch, "octave") {
+        Some(o) => Some(o.parse().map_err(|_| format!("Invalid octave {o}"))?),
+        None => None,
+    };
+    let pitch = Pitch::new(note, octave);
+    if pitch.on_harp() {
+        Ok(pitch)
+    } else {
+        Err(format!(
+            "{pitch} is not on the harp, which runs from C1 to G7"
+        ))
+    }
+}
+
+fn read_duration(node: Node) -> Result<i64, String> {
+    match child_text(node, "duration") {
+        Some(d) => d.parse().map_err(|_| format!("Invalid duration {d}")),
+        None => Ok(0),
+    }
+}
+
+// <harp-pedals> lists pedal-tunings, possibly only some of them.
+fn read_harp_pedals(pedals: Node) -> Result<Harp, String> {
+    let mut harp = [None; 7];
+    let tunings = pedals.children().filter(|c| c.has_tag_name("pedal-tuning"));
+    for tuning in tunings {
+        let step =
+            child_text(tuning, "pedal-step").ok_or("Pedal without a step")?;
+        let note = Note {
+            name: read_step(step)?,
+            accidental: read_alter(child_text(tuning, "pedal-alter"))?,
+        };
+        set_pedal(&mut harp, note);
+    }
+    Ok(harp)
+}
+
+// <sound tempo="..."/> is in quarter notes per minute, maybe fractional.
+fn read_tempo(sound: Node) -> Result<Option<Tempo>, String> {
+    match sound.attribute("tempo") {
+        None => Ok(None),
+        Some(t) => match t.trim().parse::<f64>() {
+            Ok(bpm) if bpm >= 1.0 => {
+                Ok(Some(Tempo::new(QUARTER, bpm.round() as usize)))
+            }
+            _ => Err(format!("Invalid tempo {t}")),
+        },
+    }
+}
+
+fn find_harp_pedals<'a, 'i>(direction: Node<'a, 'i>) -> Option<Node<'a, 'i>> {
+    direction
+        .descendants()
+        .find(|d| d.has_tag_name("harp-pedals"))
+}
+
+// Group notes by onset, so chords, voices and both staves become beats.
+// Pedal diagrams after the first note are checkpoints on the next beat,
+// waiting in checkpoint if that is in a later measure.
+fn read_measure(
+    measure: Node,
+    this: bool,
+    start: &mut Option<Harp>,
+    checkpoint: &mut Option<Harp>,
+    seen_notes: &mut bool,
+    divisions: &mut i64,
+) -> Result<Measure, String> {
+    let mut onsets: BTreeMap<i64, Vec<NoteRequest>> = BTreeMap::new();
+    let mut tempos: BTreeMap<i64, Tempo> = BTreeMap::new();
+    let mut diagrams: BTreeMap<i64, Harp> = BTreeMap::new();
+    // How long the longest note at each onset lasts, ties included.
+    let mut lengths: BTreeMap<i64, i64> = BTreeMap::new();
+    let mut time = 0;
+    let mut last_onset = 0;
+    let mut measure_end = 0;
+    for elem in measure.children().filter(|c| c.is_element()) {
+        match elem.tag_name().name() {
+            "note" => {
+                if child(elem, "cue").is_some() {
+                    continue;
+                }
+                // The rest of a tied note is not a new beat.
+                let tied = elem.children().any(|c| {
+                    c.has_tag_name("tie") && c.attribute("type") == Some("stop")
+                });
+                if tied {
+                    let d = read_duration(elem)?;
+                    let at = if child(elem, "chord").is_some() {
+                        last_onset
+                    } else {
+                        time
+                    };
+                    for (onset, length) in lengths.iter_mut() {
+                        if *onset + *length == at {
+                            *length += d;
+                        }
+                    }
+                    if child(elem, "chord").is_none() {
+                        last_onset = time;
+                        time += d;
+                        measure_end = measure_end.max(time);
+                    }
+                    continue;
+                }
+                let onset = if child(elem, "chord").is_some() {
+                    last_onset
+                } else {
+                    time
+                };
+                let request = if child(elem, "rest").is_some() {
+                    Rest
+                } else if let Some(pitch) = child(elem, "pitch") {
+                    let pitch = read_pitch(pitch)?;
+                    *seen_notes = true;
+                    if this {
+                        This(pitch)
+                    } else {
+                        Any(pitch)
+                    }
+                } else {
+                    // Unpitched percussion on the soundboard.
+                    Rest
+                };
+                if request != Rest {
+                    let length = lengths.entry(onset).or_default();
+                    *length = (*length).max(read_duration(elem)?);
+                }
+                onsets.entry(onset).or_default().push(request);
+                if child(elem, "chord").is_none() {
+                    last_onset = time;
+                    time += read_duration(elem)?;
+                }
+            }
+            "backup" => time -= read_duration(elem)?,
+            "forward" => time += read_duration(elem)?,
+            "attributes" => {
+                if let Some(d) = child_text(elem, "divisions") {
+                    *divisions = d
+                        .parse()
+                        .ok()
+                        .filter(|d| *d > 0)
+                        .ok_or(format!("Invalid divisions {d}"))?;
+                }
+            }
+            "sound" => {
+                if let Some(t) = read_tempo(elem)? {
+                    tempos.insert(time, t);
+                }
+            }
+            "direction" => {
+                if let Some(t) = child(elem, "sound").map(read_tempo) {
+                    if let Some(t) = t? {
+                        tempos.insert(time, t);
+                    }
+                }
+                if let Some(pedals) = find_harp_pedals(elem) {
+                    let pedals = read_harp_pedals(pedals)?;
+                    if !*seen_notes && start.is_none() {
+                        *start = Some(pedals);
+                    } else {
+                        let at = diagrams.entry(time).or_insert([None; 7]);
+                        *at = update_harp(*at, pedals);
+                    }
+                }
+            }
+            _ => (),
+        }
+        measure_end = measure_end.max(time);
+    }
+    // Each beat lasts until the next one starts.
+    let ends = onsets
+        .keys()
+        .skip(1)
+        .copied()
+        .chain([measure_end])
+        .collect_vec();
+    let mut diagrams = diagrams.into_iter().peekable();
+    let mut wait = |until: i64, checkpoint: &mut Option<Harp>| {
+        while let Some((_, d)) = diagrams.next_if(|(t, _)| *t <= until) {
+            *checkpoint = Some(update_harp(checkpoint.unwrap_or([None; 7]), d));
+        }
+    };
+    let mut out = Vec::with_capacity(onsets.len());
+    for ((onset, mut beat), end) in onsets.into_iter().zip(ends) {
+        wait(onset, checkpoint);
+        if beat.iter().any(|r| *r != Rest) {
+            beat.retain(|r| *r != Rest);
+        } else {
+            beat = vec![Rest];
+        }
+        let to_ticks =
+            |t: i64| t.max(0) as usize * QUARTER / *divisions as usize;
+        out.push(BeatRequest {
+            notes: beat,
+            duration: Some(to_ticks(end - onset)),
+            sustain: Some(to_ticks(lengths.get(&onset).copied().unwrap_or(0))),
+            // The latest tempo marking at or before the beat.
+            tempo: tempos.range(..=onset).next_back().map(|(_, t)| *t),
+            directives: Directives::default(),
+            checkpoint: checkpoint.take(),
+            gliss: false,
+            written: None,
+        });
+    }
+    wait(i64::MAX, checkpoint);
+    if out.is_empty() {
+        out.push(BeatRequest::new(vec![Rest], Some(QUARTER)));
+    }
+    Ok(out)
+}
+
+// Reads the first part of an uncompressed partwise MusicXML file.
+// If this is set, written spellings are kept as if marked with *.
+pub fn parse_musicxml(s: &str, this: bool) -> Result<Parsed, String> {
+    // Exported files usually carry a DOCTYPE.
+    let options = ParsingOptions {
+        allow_dtd: true,
+        ..ParsingOptions::default()
+    };
+    let doc =
+        Document::parse_with_options(s, options).map_err(|e| e.to_string())?;
+    let root = doc.root_element();
+    if !root.has_tag_name("score-partwise") {
+        return Err(format!(
+            "Expected score-partwise, found {}",
+            root.tag_name().name()
+        ));
+    }
+    let part = child(root, "part").ok_or("No parts found")?;
+    let mut start = None;
+    let mut checkpoint = None;
+    let mut seen_notes = false;
+    let mut divisions = 1;
+    let mut measures = Vec::new();
+    for measure in part.children().filter(|c| c.has_tag_name("measure")) {
+        measures.push(read_measure(
+            measure,
+            this,
+            &mut start,
+            &mut checkpoint,
+            &mut seen_notes,
+            &mut divisions,
+        )?);
+    }
+    if measures.is_empty() {
+        return Err("No measures found".to_string());
+    }
+    // A diagram after the last note is where the piece must end.
+    Ok(to_parsed(start, measures, checkpoint))
+}
+
+fn alter(accidental: Accidental) -> i8 {
+    match accidental {
+        Flat => -1,
+        Natural => 0,
+        Sharp => 1,
+    }
+}
+
+fn accidental_name(accidental: Accidental) -> &'static str {
+    match accidental {
+        Flat => "flat",
+        Natural => "natural",
+        Sharp => "sharp",
+    }
+}
+
+fn harp_pedals_direction(lines: &mut Vec<String>, diagram: Harp) {
+    lines.push("      <direction placement=\"below\">".to_string());
+    lines.push("        <direction-type>".to_string());
+    lines.push("          <harp-pedals>".to_string());
+    for note in harp_to_notes(diagram) {
+        lines.push(format!(
+            "            <pedal-tuning><pedal-step>{}</pedal-step>\
+             <pedal-alter>{}</pedal-alter></pedal-tuning>",
+            note.name,
+            alter(note.accidental)
+        ));
+    }
+    lines.push("          </harp-pedals>".to_string());
+    lines.push("        </direction-type>".to_string());
+    lines.push("      </direction>".to_string());
+}
+
+fn words_direction(lines: &mut Vec<String>, changes: &[Note]) {
+    if changes.is_empty() {
+        return;
+    }
+    let words = changes.iter().map(|n| n.to_string()).join(" ");
+    lines.push("      <direction placement=\"below\">".to_string());
+    lines.push(format!(
+        "        <direction-type><words>{words}</words></direction-type>"
+    ));
+    lines.push("      </direction>".to_string());
+}
+
+// Right foot above left foot, as in the LilyPond output.
+fn pedal_directions(lines: &mut Vec<String>, changes: &[Note]) {
+    let (rights, lefts): (Vec<Note>, Vec<Note>) =
+        changes.iter().partition(|n| n.is_right());
+    words_direction(lines, &rights);
+    words_direction(lines, &lefts);
+}
+
+fn type_name(base: usize) -> &'static str {
+    match base {
+        1 => "whole",
+        2 => "half",
+        4 => "quarter",
+        8 => "eighth",
+        16 => "16th",
+        32 => "32nd",
+        _ => "64th",
+    }
+}
+
+// Where a note sits in a chain of tied notes.
+#[derive(Copy, Clone)]
+struct Tie {
+    start: bool,
+    stop: bool,
+}
+
+// Written accidentals last until the barline.
+fn add_xml_note(
+    lines: &mut Vec<String>,
+    pitch: Option<Pitch>,
+    chord: bool,
+    (base, dots): (usize, usize),
+    tie: Tie,
+    written: &mut [Accidental; 7],
+) {
+    lines.push("      <note>".to_string());
+    if chord {
+        lines.push("        <chord/>".to_string());
+    }
+    let note = pitch.map(|p| p.note);
+    match pitch {
+        Some(Pitch { note, octave }) => {
+            lines.push("        <pitch>".to_string());
+            lines.push(format!("          <step>{}</step>", note.name));
+            if note.accidental != Natural {
+                lines.push(format!(
+                    "          <alter>{}</alter>",
+                    alter(note.accidental)
+                ));
+            }
+            lines.push(format!(
+                "          <octave>{}</octave>",
+                octave.unwrap_or(4)
+            ));
+            lines.push("        </pitch>".to_string());
+        }
+        None => lines.push("        <rest/>".to_string()),
+    }
+    let ticks = note_value(base, dots).unwrap_or(QUARTER);
+    lines.push(format!("        <duration>{ticks}</duration>"));
+    if tie.stop {
+        lines.push("        <tie type=\"stop\"/>".to_string());
+    }
+    if tie.start {
+        lines.push("        <tie type=\"start\"/>".to_string());
+    }
+    lines.push(format!("        <type>{}</type>", type_name(base)));
+    for _ in 0..dots {
+        lines.push("        <dot/>".to_string());
+    }
+    if let Some(note) = note {
+        let idx = name_to_usize(note.name);
+        if written[idx] != note.accidental {
+            written[idx] = note.accidental;
+            lines.push(format!(
+                "        <accidental>{}</accidental>",
+                accidental_name(note.accidental)
+            ));
+        }
+    }
+    if tie.start || tie.stop {
+        lines.push("        <notations>".to_string());
+        if tie.stop {
+            lines.push("          <tied type=\"stop\"/>".to_string());
+        }
+        if tie.start {
+            lines.push("          <tied type=\"start\"/>".to_string());
+        }
+        lines.push("        </notations>".to_string());
+    }
+    lines.push("      </note>".to_string());
+}
+
+// Durations that aren't a single note value are written as tied notes.
+fn add_xml_measure(
+    lines: &mut Vec<String>,
+    measure: Vec<(Vec<Pitch>, usize)>,
+    changes: &mut std::slice::Iter<Vec<Note>>,
+) {
+    let mut written = [Natural; 7];
+    for (beat, ticks) in measure {
+        if let Some(c) = changes.next() {
+            pedal_directions(lines, c);
+        }
+        let values = split_value(ticks);
+        for (j, value) in values.iter().enumerate() {
+            if beat.is_empty() {
+                let tie = Tie {
+                    start: false,
+                    stop: false,
+                };
+                add_xml_note(lines, None, false, *value, tie, &mut written);
+            }
+            let tie = Tie {
+                start: j + 1 < values.len(),
+                stop: j > 0,
+            };
+            for (i, pitch) in beat.iter().enumerate() {
+                add_xml_note(
+                    lines,
+                    Some(*pitch),
+                    i > 0,
+                    *value,
+                    tie,
+                    &mut written,
+                );
+            }
+        }
+    }
+}
+
+// The time signature for a measure of ticks, if it differs from the last.
+fn xml_time(ticks: usize, time: &mut Option<(usize, usize)>) -> Option<String> {
+    let this = time_signature(ticks);
+    if ticks == 0 || *time == Some(this) {
+        return None;
+    }
+    *time = Some(this);
+    let (beats, base) = this;
+    Some(format!(
+        "        <time><beats>{beats}</beats><beat-type>{base}</beat-type></time>"
+    ))
+}
+
+// Mirrors make_ly_file_, with time signatures from the measure lengths.
+pub fn make_xml_file(
+    treble: Vec<Vec<(Vec<Pitch>, usize)>>,
+    start: Harp,
+    end: Harp,
+    changes: &Pedals,
+) -> String {
+    let mut lines: Vec<String> = [
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"no\"?>",
+        "<!DOCTYPE score-partwise PUBLIC \
+         \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+         \"http://www.musicxml.org/dtds/partwise.dtd\">",
+        "<score-partwise version=\"4.0\">",
+        "  <part-list>",
+        "    <score-part id=\"P1\"><part-name>Harp</part-name></score-part>",
+        "  </part-list>",
+        "  <part id=\"P1\">",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect_vec();
+    let mut pedals = changes.iter();
+    let mut time = None;
+    let num_measures = treble.len();
+    for (i, measure) in treble.into_iter().enumerate() {
+        lines.push(format!("    <measure number=\"{}\">", i + 1));
+        let length = measure.iter().map(|(_, ticks)| ticks).sum::<usize>();
+        let signature = xml_time(length, &mut time);
+        if i == 0 {
+            lines.push("      <attributes>".to_string());
+            lines.push(format!("        <divisions>{QUARTER}</divisions>"));
+            lines.push("        <key><fifths>0</fifths></key>".to_string());
+            lines.extend(signature);
+            lines.push(
+                "        <clef><sign>G</sign><line>2</line></clef>".to_string(),
+            );
+            lines.push("      </attributes>".to_string());
+            harp_pedals_direction(&mut lines, start);
+        } else if let Some(signature) = signature {
+            lines.push("      <attributes>".to_string());
+            lines.push(signature);
+            lines.push("      </attributes>".to_string());
+        }
+        add_xml_measure(&mut lines, measure, &mut pedals);
+        if i + 1 == num_measures {
+            for c in pedals.by_ref() {
+                pedal_directions(&mut lines, c);
+            }
+            harp_pedals_direction(&mut lines, end);
+        }
+        lines.push("    </measure>".to_string());
+    }
+    lines.push("  </part>".to_string());
+    lines.push("</score-partwise>".to_string());
+    lines.join("\n")
+}
@@ -3,40 +3,159 @@ use crate::prelude::*;
 use itertools::Itertools;
 use nom::{
     branch::alt,
-    character::complete::{char, multispace0, multispace1, one_of},
-    combinator::{all_consuming, map, opt, value},
-    error::ParseError,
-    multi::{count, many0, separated_list1},
-    sequence::{delimited, preceded},
+    bytes::complete::{tag, take_till},
+    character::complete::{char, digit1, multispace0, multispace1, one_of},
+    combinator::{all_consuming, cut, map, map_opt, map_res, opt, peek, value},
+    error::{
+        context, ContextError, ErrorKind, ParseError, VerboseError,
+        VerboseErrorKind,
+    },
+    multi::{count, many0, many0_count, many1, many1_count, separated_list1},
+    sequence::{delimited, pair, preceded, terminated, tuple},
     Finish, IResult,
 };
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::ops::Range;
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+type Res<'a, O> = IResult<&'a str, O, VerboseError<&'a str>>;
+
+// Where something was written, as how much of the text was left before
+// and after it, since that is all a parser sees.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Span {
+    before: usize,
+    after: usize,
+}
+
+impl Span {
+    fn new(before: &str, after: &str) -> Span {
+        Span {
+            before: before.len(),
+            after: after.len(),
+        }
+    }
+
+    // As offsets into the whole text, len long.
+    fn range(&self, len: usize) -> Range<usize> {
+        len - self.before..len - self.after
+    }
+}
+
+// Where a beat and each of its notes were written, to annotate them.
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct Written {
+    pub beat: Span,
+    pub notes: Vec<Span>,
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum NoteRequest {
-    This(Note),
-    Any(Note),
+    This(Pitch),
+    Any(Pitch),
     Rest,
+    // Stop every string ringing before the beat is played.
+    Damp,
+    // A bisbigliando, the pitch on both strings that can sound it.
+    Bisb(Pitch),
+}
+
+impl NoteRequest {
+    // The notes it needs the pedals to spell as written.
+    fn fixed(&self) -> Vec<Pitch> {
+        match self {
+            This(p) => vec![*p],
+            Bisb(p) => pc_to_notes(note_to_pc(p.note))
+                .into_iter()
+                .map(|n| p.respell(n))
+                .collect_vec(),
+            _ => vec![],
+        }
+    }
+}
+
+// Lines and columns start at 1, columns count characters.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SyntaxError {
+    pub line: usize,
+    pub column: usize,
+    // The offending token, empty at the end of input.
+    pub text: String,
+    pub expected: String,
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: expected {}, found ",
+            self.line, self.column, self.expected
+        )?;
+        if self.text.is_empty() {
+            write!(f, "end of input")
+        } else {
+            write!(f, "'{}'", self.text)
+        }
+    }
+}
+
+impl std::error::Error for SyntaxError {}
+
+impl SyntaxError {
+    // Show the offending line of the original source with a caret under
+    // the error, in the style of rustc.
+    pub fn annotate(&self, source: &str) -> String {
+        let line = source.lines().nth(self.line - 1).unwrap_or("");
+        let number = self.line.to_string();
+        let pad = " ".repeat(number.len());
+        let indent: String = line
+            .chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width = self.text.chars().count().clamp(1, 40);
+        format!(
+            "{pad} --> {}:{}\n{pad} |\n{number} | {line}\n{pad} | {indent}{} expected {}",
+            self.line,
+            self.column,
+            "^".repeat(width),
+            self.expected
+        )
+    }
 }
 
 pub struct Parsed {
     pub start: Option<Harp>,
-    pub this_any: Vec<Vec<(Vec<Note>, Vec<PitchClass>)>>,
+    // Each measure's beats.
+    pub music: Vec<Vec<BeatInput>>,
     pub end: Option<Harp>,
 }
 
+// The solver sees the measures run together, and unset diagrams as free.
+impl From<&Parsed> for MusicInput {
+    fn from(parsed: &Parsed) -> MusicInput {
+        MusicInput {
+            diagram: parsed.start.unwrap_or([None; 7]),
+            music: parsed.music.concat(),
+            goal: parsed.end.unwrap_or([None; 7]),
+        }
+    }
+}
+
 // Allow but don't require space before and after, includes newlines.
-fn ws<'a, F: 'a, O, E: ParseError<&'a str>>(
+fn ws<'a, F, O, E: ParseError<&'a str>>(
     inner: F,
 ) -> impl FnMut(&'a str) -> IResult<&'a str, O, E>
 where
-    F: Fn(&'a str) -> IResult<&'a str, O, E>,
+    F: 'a + Fn(&'a str) -> IResult<&'a str, O, E>,
 {
     delimited(multispace0, inner, multispace0)
 }
 
 // Accepts a pedal setting, returns the appropriate u8 representing it.
-fn pedal_setting(s: &str) -> IResult<&str, Option<Accidental>> {
-    let (rem, c) = one_of("~^-v")(s)?;
+fn pedal_setting(s: &str) -> Res<'_, Option<Accidental>> {
+    let (rem, c) =
+        context("a pedal setting (~, ^, - or v)", one_of("~^-v"))(s)?;
     let setting = match c {
         '~' => None,
         '^' => Some(Flat),
@@ -48,10 +167,15 @@ fn pedal_setting(s: &str) -> IResult<&str, Option<Accidental>> {
 }
 
 // Accepts 3 pedal settings, followed by |, followed by 4 settings.
-fn diagram(s: &str) -> IResult<&str, Harp> {
-    let (rem, left) = count(pedal_setting, 3)(s)?;
-    let (rem, _) = char('|')(rem)?;
-    let (rem, right) = count(pedal_setting, 4)(rem)?;
+// Nothing else starts with a pedal setting, so commit after the first.
+fn diagram(s: &str) -> Res<'_, Harp> {
+    let (rem, first) = pedal_setting(s)?;
+    let (rem, (mut left, _, right)) = cut(tuple((
+        count(pedal_setting, 2),
+        context("'|' after three pedal settings", char('|')),
+        count(pedal_setting, 4),
+    )))(rem)?;
+    left.insert(0, first);
     let mut harp = [None; 7];
     for (i, c) in left.into_iter().enumerate() {
         harp[i] = c;
@@ -62,12 +186,16 @@ fn diagram(s: &str) -> IResult<&str, Harp> {
     Ok((rem, harp))
 }
 
-fn rest(s: &str) -> IResult<&str, NoteRequest> {
+fn rest(s: &str) -> Res<'_, NoteRequest> {
     value(Rest, one_of("rR"))(s)
 }
 
+fn damp(s: &str) -> Res<'_, NoteRequest> {
+    value(Damp, char('/'))(s)
+}
+
 // Accepts a pitch name, returns the name.
-fn pitch(s: &str) -> IResult<&str, Name> {
+fn note_name(s: &str) -> Res<'_, Name> {
     let (rem, c) = one_of("abcdefgABCDEFG")(s)?;
     let name = match c {
         'A' | 'a' => Name::A,
@@ -83,7 +211,7 @@ fn pitch(s: &str) -> IResult<&str, Name> {
 }
 
 // Accepts a pitch modifier, returns the modifier.
-fn modifier(s: &str) -> IResult<&str, Accidental> {
+fn modifier(s: &str) -> Res<'_, Accidental> {
     let (rem, c) = one_of("fb♭n♮s#♯")(s)?;
     let modif = match c {
         'f' | 'b' | '♭' => Flat,
@@ -95,8 +223,8 @@ fn modifier(s: &str) -> IResult<&str, Accidental> {
 }
 
 // Accepts a pitch name and, if present, modifier, returns a note.
-fn note(s: &str) -> IResult<&str, Note> {
-    let (rem, name) = pitch(s)?;
+fn note(s: &str) -> Res<'_, Note> {
+    let (rem, name) = note_name(s)?;
     let (rem, m) = opt(modifier)(rem)?;
     let modifier = match m {
         Some(x) => x,
@@ -111,41 +239,281 @@ fn note(s: &str) -> IResult<&str, Note> {
     ))
 }
 
+// Scientific, as in Eb3, or LilyPond's absolute octave marks, as in c'.
+// Both put middle C in octave 4.
+fn octave(s: &str) -> Res<'_, u8> {
+    alt((
+        map_res(one_of("0123456789"), |c| c.to_string().parse::<u8>()),
+        // Too many marks are off the harp either way.
+        map(many1_count(char('\'')), |n| (3 + n).min(9) as u8),
+        map(many1_count(char(',')), |n| 3usize.saturating_sub(n) as u8),
+    ))(s)
+}
+
+// Accepts a note and, if present, its octave, which must be on the harp.
+fn pitch(s: &str) -> Res<'_, Pitch> {
+    let (rem, (note, octave)) = pair(note, opt(octave))(s)?;
+    let pitch = Pitch::new(note, octave);
+    if pitch.on_harp() {
+        Ok((rem, pitch))
+    } else {
+        let e = VerboseError::from_error_kind(s, ErrorKind::Verify);
+        Err(nom::Err::Failure(VerboseError::add_context(
+            s,
+            "a note on the harp, from C1 to G7",
+            e,
+        )))
+    }
+}
+
 // A note that can be respelled
-fn any_note(s: &str) -> IResult<&str, NoteRequest> {
-    map(note, Any)(s)
+fn any_note(s: &str) -> Res<'_, NoteRequest> {
+    map(pitch, Any)(s)
 }
 
 // A note that cannot be respelled
-fn this_note(s: &str) -> IResult<&str, NoteRequest> {
-    map(preceded(char('*'), note), This)(s)
+fn this_note(s: &str) -> Res<'_, NoteRequest> {
+    map(
+        preceded(char('*'), cut(context("a note after '*'", pitch))),
+        This,
+    )(s)
+}
+
+// "bisb(eb)" asks for D# and Eb, so both strings can trade the note.
+fn bisb(s: &str) -> Res<'_, NoteRequest> {
+    let (rem, p) = preceded(
+        tag("bisb"),
+        cut(context(
+            "a note in brackets, such as bisb(eb)",
+            delimited(char('('), pitch, char(')')),
+        )),
+    )(s)?;
+    if pc_to_notes(note_to_pc(p.note)).len() == 2 {
+        Ok((rem, Bisb(p)))
+    } else {
+        let e = VerboseError::from_error_kind(s, ErrorKind::Verify);
+        Err(nom::Err::Failure(VerboseError::add_context(
+            s,
+            "a note on two strings (D, G and A only have one)",
+            e,
+        )))
+    }
+}
+
+fn note_request(s: &str) -> Res<'_, NoteRequest> {
+    alt((rest, damp, bisb, this_note, any_note))(s)
+}
+
+fn located_request(s: &str) -> Res<'_, (NoteRequest, Span)> {
+    let (rem, request) = note_request(s)?;
+    Ok((rem, (request, Span::new(s, rem))))
+}
+
+// A note value as in LilyPond, eg 4 or 8., returns its length in ticks.
+fn duration(s: &str) -> Res<'_, usize> {
+    preceded(
+        peek(digit1),
+        cut(context(
+            "a duration (1, 2, 4, 8, 16, 32 or 64, then any dots)",
+            map_opt(
+                pair(
+                    map_res(digit1, str::parse::<usize>),
+                    many0_count(char('.')),
+                ),
+                |(base, dots)| note_value(base, dots),
+            ),
+        )),
+    )(s)
+}
+
+fn bpm(s: &str) -> Res<'_, usize> {
+    map_opt(map_res(digit1, str::parse::<usize>), |n| {
+        (n > 0).then_some(n)
+    })(s)
+}
+
+// As in LilyPond, "\tempo 4. = 96", returns the tempo.
+fn tempo(s: &str) -> Res<'_, Tempo> {
+    preceded(
+        tag("\\tempo"),
+        cut(context(
+            "a tempo such as 4 = 120",
+            map(
+                pair(ws(duration), preceded(char('='), ws(bpm))),
+                |(unit, bpm)| Tempo::new(unit, bpm),
+            ),
+        )),
+    )(s)
+}
+
+#[derive(Copy, Clone)]
+enum Directive {
+    Change(Note),
+    HoldLeft,
+    HoldRight,
+    Hold,
+}
+
+fn directive(s: &str) -> Res<'_, Directive> {
+    alt((
+        value(Directive::HoldLeft, tag("!L")),
+        value(Directive::HoldRight, tag("!R")),
+        value(Directive::Hold, char('!')),
+        map(note, Directive::Change),
+    ))(s)
+}
+
+// Accepts "{F# !R}", returns what it asks of the beat after it.
+fn directives(s: &str) -> Res<'_, Directives> {
+    let (rem, found) = preceded(
+        char('{'),
+        cut(terminated(
+            many0(ws(directive)),
+            context("a change, '!', '!L', '!R' or '}'", char('}')),
+        )),
+    )(s)?;
+    let mut out = Directives::default();
+    for d in found {
+        match d {
+            Directive::Change(n) => out.changes.push(n),
+            Directive::HoldLeft => out.hold_left = true,
+            Directive::HoldRight => out.hold_right = true,
+            Directive::Hold => {
+                out.hold_left = true;
+                out.hold_right = true;
+            }
+        }
+    }
+    if out.is_possible() {
+        Ok((rem, out))
+    } else {
+        let e = VerboseError::from_error_kind(s, ErrorKind::Verify);
+        Err(nom::Err::Failure(VerboseError::add_context(
+            s,
+            "at most one change per foot, and none for a held foot",
+            e,
+        )))
+    }
+}
+
+enum Prefix {
+    Tempo(Tempo),
+    Directives(Directives),
+}
+
+// One beat as written, before it is split into this and any.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BeatRequest {
+    pub notes: Vec<NoteRequest>,
+    pub duration: Option<usize>,
+    // How long its notes ring, if not for its duration.
+    pub sustain: Option<usize>,
+    // A tempo marking starting at this beat.
+    pub tempo: Option<Tempo>,
+    pub directives: Directives,
+    // A pedal diagram the harp must match here.
+    pub checkpoint: Option<Harp>,
+    // Whether every string is swept, so must sound one of the notes.
+    pub gliss: bool,
+    // Only known for music read from .hrp text.
+    #[serde(skip)]
+    pub written: Option<Written>,
 }
 
-fn note_request(s: &str) -> IResult<&str, NoteRequest> {
-    alt((rest, this_note, any_note))(s)
+impl BeatRequest {
+    pub fn new(
+        notes: Vec<NoteRequest>,
+        duration: Option<usize>,
+    ) -> BeatRequest {
+        BeatRequest {
+            notes,
+            duration,
+            sustain: None,
+            tempo: None,
+            directives: Directives::default(),
+            checkpoint: None,
+            gliss: false,
+            written: None,
+        }
+    }
 }
 
-// Accepts any number of note requests, delimited by any amount of space,
-// all on the same line. "b#\tc  d \t" -> (" \t", [B#, C, D])
-fn beat(s: &str) -> IResult<&str, Vec<NoteRequest>> {
-    delimited(char('['), many0(ws(note_request)), char(']'))(s)
+// Accepts an optional tempo and directives, any number of note requests,
+// delimited by any amount of space, all on the same line, and an optional
+// duration.
+// "\tempo 4=90 {!L} [b#\tc  d \t]8." -> ([B#, C, D], Some(dotted eighth))
+// A glissando is written "gliss[c eb f# a]".
+fn beat(s: &str) -> Res<'_, BeatRequest> {
+    let (rem, prefixes) = many0(terminated(
+        alt((
+            map(tempo, Prefix::Tempo),
+            map(directives, Prefix::Directives),
+        )),
+        multispace0,
+    ))(s)?;
+    let mut tempo = None;
+    let mut directives = Directives::default();
+    for prefix in prefixes {
+        match prefix {
+            Prefix::Tempo(t) => tempo = Some(t),
+            Prefix::Directives(mut d) => {
+                directives.changes.append(&mut d.changes);
+                directives.hold_left |= d.hold_left;
+                directives.hold_right |= d.hold_right;
+            }
+        }
+    }
+    let (rem, gliss) = map(opt(tag("gliss")), |g| g.is_some())(rem)?;
+    let (rem, (notes, duration)) = pair(
+        preceded(
+            char('['),
+            cut(terminated(
+                many0(ws(located_request)),
+                context("a note, a rest or ']'", char(']')),
+            )),
+        ),
+        opt(duration),
+    )(rem)?;
+    let (notes, spans) = notes.into_iter().unzip();
+    Ok((
+        rem,
+        BeatRequest {
+            notes,
+            duration,
+            sustain: None,
+            tempo,
+            directives,
+            checkpoint: None,
+            gliss,
+            written: Some(Written {
+                beat: Span::new(s, rem),
+                notes: spans,
+            }),
+        },
+    ))
 }
 
-pub type Measure = Vec<Vec<NoteRequest>>;
+pub type Measure = Vec<BeatRequest>;
 
 // Accepts beats sepparated by at least a new line,
 // possibly with extra whitespace.
 // "b#\tc  d \t
 // \t
 // fb  | " -> ("  | ", [[B#, C, D], [Fb]])
-fn measure(s: &str) -> IResult<&str, Measure> {
-    separated_list1(multispace1, beat)(s)
+// A measure may start with a pedal diagram, which its first beat must match.
+// "---|---- [c] [d]" -> [[C], [D]], with the diagram on [C]
+fn measure(s: &str) -> Res<'_, Measure> {
+    let (rem, checkpoint) = opt(terminated(diagram, multispace0))(s)?;
+    let (rem, mut beats) = separated_list1(multispace1, beat)(rem)?;
+    beats[0].checkpoint = checkpoint;
+    Ok((rem, beats))
 }
 
-fn music(s: &str) -> IResult<&str, Vec<Measure>> {
+fn music(s: &str) -> Res<'_, Vec<Measure>> {
     delimited(
         opt(ws(char('|'))),
-        separated_list1(ws(char('|')), measure),
+        separated_list1(ws(char('|')), context("a beat", measure)),
         opt(ws(char('|'))),
     )(s)
 }
@@ -161,60 +529,327 @@ fn strip_comments(s: &str) -> String {
     s.lines().map(strip_line).join("\n")
 }
 
-#[allow(clippy::type_complexity)]
+// A file, or a section of one, as written.
+struct CleanFile {
+    start: Option<Harp>,
+    mid: Vec<Measure>,
+    end: Option<Harp>,
+    // Where its diagrams are, or would go.
+    start_at: Span,
+    end_at: Span,
+}
+
 // Parse an already processed file.
-fn parse_clean_file(
-    s: &str,
-) -> IResult<&str, (Option<Harp>, Vec<Measure>, Option<Harp>)> {
-    let (rem, start) = opt(ws(diagram))(s)?;
-    let (rem, body) = ws(music)(rem)?;
-    let (rem, end) = opt(ws(diagram))(rem)?;
-    Ok((rem, (start, body, end)))
-}
-
-#[allow(clippy::type_complexity)]
-fn pre_parse(
-    s: &str,
-) -> Result<(Option<Harp>, Vec<Measure>, Option<Harp>), String> {
+fn parse_clean_file(s: &str) -> Res<'_, CleanFile> {
+    let (rem, _) = multispace0(s)?;
+    let (after, start) = opt(diagram)(rem)?;
+    let start_at = Span::new(rem, after);
+    let (rem, _) = multispace0(after)?;
+    let (after, mid) = music(rem)?;
+    // Just after the last beat or bar line, not the space after it.
+    let played = rem[..rem.len() - after.len()].trim_end();
+    let last = &rem[played.len()..];
+    let (rem, _) = multispace0(after)?;
+    let (after, end) = opt(diagram)(rem)?;
+    let end_at = match end {
+        Some(_) => Span::new(rem, after),
+        None => Span::new(last, last),
+    };
+    let (rem, _) = multispace0(after)?;
+    Ok((
+        rem,
+        CleanFile {
+            start,
+            mid,
+            end,
+            start_at,
+            end_at,
+        },
+    ))
+}
+
+// \section "Prelude", returns the title.
+fn section_title(s: &str) -> Res<'_, String> {
+    preceded(
+        tag("\\section"),
+        cut(context(
+            "a title in quotes",
+            delimited(
+                pair(multispace0, char('"')),
+                map(take_till(|c| c == '"' || c == '\n'), str::to_string),
+                pair(char('"'), multispace0),
+            ),
+        )),
+    )(s)
+}
+
+// A file without titles is a single untitled section.
+fn parse_clean_sections(s: &str) -> Res<'_, Vec<(Option<String>, CleanFile)>> {
+    alt((
+        many1(map(pair(ws(section_title), parse_clean_file), |(t, f)| {
+            (Some(t), f)
+        })),
+        map(parse_clean_file, |f| vec![(None, f)]),
+    ))(s)
+}
+
+fn describe(kind: &VerboseErrorKind) -> String {
+    match kind {
+        VerboseErrorKind::Context(c) => c.to_string(),
+        VerboseErrorKind::Char(c) => format!("'{c}'"),
+        VerboseErrorKind::Nom(ErrorKind::Eof) => {
+            "a beat, '|' or a pedal diagram".to_string()
+        }
+        VerboseErrorKind::Nom(k) => k.description().to_lowercase(),
+    }
+}
+
+// Comments only ever remove the end of a line, so positions in the
+// stripped text are positions in the original.
+fn syntax_error(clean: &str, e: &VerboseError<&str>) -> SyntaxError {
+    let rem = e.errors.first().map(|(i, _)| *i).unwrap_or("");
+    let consumed = &clean[..clean.len() - rem.len()];
+    let line = consumed.matches('\n').count() + 1;
+    let column = match consumed.rsplit_once('\n') {
+        Some((_, tail)) => tail.chars().count() + 1,
+        None => consumed.chars().count() + 1,
+    };
+    let is_delimiter = |c: char| "[]|".contains(c);
+    let text: String = match rem.chars().next() {
+        Some(c) if is_delimiter(c) => c.to_string(),
+        _ => rem
+            .chars()
+            .take_while(|c| !c.is_whitespace() && !is_delimiter(*c))
+            .collect(),
+    };
+    let expected = e
+        .errors
+        .iter()
+        .find(|(_, k)| matches!(k, VerboseErrorKind::Context(_)))
+        .or(e.errors.first())
+        .map(|(_, k)| describe(k))
+        .unwrap_or_else(|| "valid input".to_string());
+    SyntaxError {
+        line,
+        column,
+        text,
+        expected,
+    }
+}
+
+fn pre_parse(s: &str) -> Result<CleanFile, SyntaxError> {
     let t = strip_comments(s);
     let r = all_consuming(parse_clean_file)(&t).finish();
     match r {
         Ok((_, y)) => Ok(y),
-        Err(x) => Err(x.to_string()),
+        Err(x) => Err(syntax_error(&t, &x)),
     }
 }
 
-// List of rest, this, and any, to list of (this, any)
-fn split_requests(
-    requests: Vec<Measure>,
-) -> Vec<Vec<(Vec<Note>, Vec<PitchClass>)>> {
+// Each beat as written, to what the solver needs to know of it.
+// As in LilyPond, a beat without a duration lasts as long as the last one,
+// and notes ring for their whole beat, unless we know better.
+// Tempo markings also last until the next one, and the music before the
+// first is at the default tempo. Without any, there are no times at all.
+pub(crate) fn split_beats(requests: Vec<Measure>) -> Vec<Vec<BeatInput>> {
+    let timed = requests.iter().flatten().any(|beat| beat.tempo.is_some());
+    let mut duration = QUARTER;
+    let mut tempo = DEFAULT_TEMPO;
     let mut out = Vec::with_capacity(requests.len());
     for measure in requests {
         let mut measure_contents = Vec::with_capacity(measure.len());
         for beat in measure {
-            let mut this = Vec::with_capacity(beat.len());
-            let mut any = Vec::with_capacity(beat.len());
-            for req in beat {
+            duration = beat.duration.unwrap_or(duration);
+            tempo = beat.tempo.unwrap_or(tempo);
+            let mut this = Vec::with_capacity(beat.notes.len());
+            let mut any = Vec::with_capacity(beat.notes.len());
+            let mut pitches = (vec![], vec![]);
+            let mut bisb = vec![];
+            for req in &beat.notes {
                 match req {
-                    This(n) => this.push(n),
-                    Any(n) => any.push(note_to_pc(n)),
-                    Rest => (),
+                    Any(p) => {
+                        any.push(note_to_pc(p.note));
+                        pitches.1.push(*p);
+                    }
+                    _ => {
+                        this.extend(req.fixed().iter().map(|p| p.note));
+                        pitches.0.extend(req.fixed());
+                    }
+                }
+                if let Bisb(p) = req {
+                    bisb.push(note_to_pc(p.note));
                 }
             }
-            measure_contents.push((this, any));
+            measure_contents.push(BeatInput {
+                this_any: (this, any),
+                pitches,
+                duration,
+                millis: timed.then(|| tempo.millis(duration)),
+                sustain: beat.sustain.unwrap_or(duration),
+                damp: beat.notes.contains(&Damp),
+                directives: beat.directives,
+                checkpoint: beat.checkpoint,
+                gliss: beat.gliss,
+                bisb,
+            });
         }
         out.push(measure_contents);
     }
     out
 }
 
-pub fn parse(s: &str) -> Result<Parsed, String> {
+pub(crate) fn to_parsed(
+    start: Option<Harp>,
+    mid: Vec<Measure>,
+    end: Option<Harp>,
+) -> Parsed {
+    Parsed {
+        start,
+        music: split_beats(mid),
+        end,
+    }
+}
+
+pub fn parse(s: &str) -> Result<Parsed, SyntaxError> {
     match pre_parse(s) {
-        Ok((start, mid, end)) => Ok(Parsed {
-            start,
-            this_any: split_requests(mid),
-            end,
-        }),
+        Ok(f) => Ok(to_parsed(f.start, f.mid, f.end)),
         Err(x) => Err(x),
     }
 }
+
+// A movement of a longer piece, with its own diagrams.
+pub struct Section {
+    pub title: Option<String>,
+    pub parsed: Parsed,
+}
+
+// Like parse, but for files split up with \section "Title".
+pub fn parse_sections(s: &str) -> Result<Vec<Section>, SyntaxError> {
+    let t = strip_comments(s);
+    let r = all_consuming(parse_clean_sections)(&t).finish();
+    match r {
+        Ok((_, sections)) => Ok(sections
+            .into_iter()
+            .map(|(title, f)| Section {
+                title,
+                parsed: to_parsed(f.start, f.mid, f.end),
+            })
+            .collect_vec()),
+        Err(x) => Err(syntax_error(&t, &x)),
+    }
+}
+
+// How a note is typed, eg Bb or F#.
+fn typed_note(note: Note) -> String {
+    note.to_string()
+        .replace('♭', "b")
+        .replace('♯', "#")
+        .replace('♮', "")
+}
+
+// Comments only ever remove the end of a line, so an offset into the
+// stripped text t is at the same line and column of the original s.
+fn original_offset(s: &str, t: &str, offset: usize) -> usize {
+    let line = t[..offset].matches('\n').count();
+    let column = offset - t[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_start = match line {
+        0 => 0,
+        _ => s
+            .match_indices('\n')
+            .nth(line - 1)
+            .map_or(s.len(), |(i, _)| i + 1),
+    };
+    line_start + column
+}
+
+// Writes pedalings back into the .hrp text they were solved from, one
+// for each section: full diagrams at either end, changes before the
+// beats that make them, and every note spelled as chosen, with a *.
+// Comments and layout are kept, and solving it again gives the same plan.
+pub fn annotate(s: &str, plans: &[&Candidate]) -> Result<String, SyntaxError> {
+    let t = strip_comments(s);
+    let r = all_consuming(parse_clean_sections)(&t).finish();
+    let sections = match r {
+        Ok((_, sections)) => sections,
+        Err(x) => return Err(syntax_error(&t, &x)),
+    };
+    let at = |offset: usize| original_offset(s, &t, offset);
+    let range = |span: Span| {
+        let r = span.range(t.len());
+        at(r.start)..at(r.end)
+    };
+    let mut edits: Vec<(Range<usize>, String)> = vec![];
+    for ((_, file), plan) in sections.iter().zip(plans) {
+        let start = pedal_diagram(plan.diagram);
+        edits.push(match file.start {
+            Some(_) => (range(file.start_at), start),
+            None => (range(file.start_at), format!("{start}\n")),
+        });
+        for (i, beat) in file.mid.iter().flatten().enumerate() {
+            let Some(written) = &beat.written else {
+                continue;
+            };
+            let changes = plan.pedals[i]
+                .iter()
+                .filter(|n| !beat.directives.changes.contains(n))
+                .map(|n| typed_note(*n))
+                .collect_vec();
+            if !changes.is_empty() {
+                let b = range(written.beat).start;
+                edits.push((b..b, format!("{{{}}} ", changes.join(" "))));
+            }
+            // A glissando's spelling is its pedals, not its notes.
+            if beat.gliss {
+                continue;
+            }
+            for (request, span) in beat.notes.iter().zip(&written.notes) {
+                let Any(p) = request else {
+                    continue;
+                };
+                let Some(n) = harp_to_notes(plan.spelling[i])
+                    .into_iter()
+                    .find(|n| note_to_pc(*n) == note_to_pc(p.note))
+                else {
+                    continue;
+                };
+                let r = range(*span);
+                let lower = s[r.clone()].starts_with(char::is_lowercase);
+                let mut name = typed_note(n);
+                if lower {
+                    name = name.to_lowercase();
+                }
+                let octave = p.respell(n).octave;
+                let octave = octave.map(|o| o.to_string()).unwrap_or_default();
+                edits.push((r, format!("*{name}{octave}")));
+            }
+        }
+        // A new diagram goes on its own line, after any comment.
+        let end = pedal_diagram(plan.destination);
+        edits.push(match file.end {
+            Some(_) => (range(file.end_at), end),
+            None => {
+                let last = file.end_at.range(t.len()).end;
+                match t[last..].find('\n') {
+                    Some(i) => {
+                        let next = at(last + i + 1);
+                        (next..next, format!("{end}\n"))
+                    }
+                    None if s.ends_with('\n') => {
+                        (s.len()..s.len(), format!("{end}\n"))
+                    }
+                    None => (s.len()..s.len(), format!("\n{end}")),
+                }
+            }
+        });
+    }
+    edits.sort_by_key(|(r, _)| r.start);
+    let mut out = String::with_capacity(s.len());
+    let mut done = 0;
+    for (r, text) in edits {
+        out.push_str(&s[done..r.start]);
+        out.push_str(&text);
+        done = r.end;
+    }
+    out.push_str(&s[done..]);
+    Ok(out)
+}
@@ -1,7 +1,12 @@
+use crate::cost::Ledger;
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 
+pub use crate::prelude::duration::*;
 pub use crate::prelude::harp::*;
 pub use crate::prelude::note::*;
+pub use crate::prelude::packed::*;
+pub use crate::prelude::pitch::*;
 pub use crate::prelude::pitch_class::*;
 
 pub use note::Accidental::*;
@@ -13,8 +18,11 @@ pub use note::Accidental::*;
 // or if enharmonics should be treated identically.
 // Harp - A collection of upto one note per scale degree.
 
+pub mod duration;
 pub mod harp;
 pub mod note;
+pub mod packed;
+pub mod pitch;
 pub mod pitch_class;
 
 pub type Pedals = Vec<Vec<Note>>;
@@ -31,86 +39,110 @@ pub fn unzip_pedals(pedals: &Pedals) -> (Pedals, Pedals) {
     (lefts, rights)
 }
 
+#[derive(Clone, Debug, Default)]
 pub struct MusicInput {
     pub diagram: Harp,
-    // presets, other pitches
-    pub music: Vec<(Vec<Note>, Vec<PitchClass>)>,
+    pub music: Vec<BeatInput>,
     pub goal: Harp,
 }
 
-#[derive(Clone, Debug)]
-pub struct CandidateBuilder {
-    pub diagram: Option<Harp>,
-    pub destination: Option<Harp>,
-    pub spelling: Option<Vec<Harp>>,
-    // Should be one longer than spelling, last is required changes for goal.
-    pub pedals: Option<Pedals>,
-    pub cost: Option<usize>,
+// One beat of the music, and all that was written about it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BeatInput {
+    // presets, other pitches
+    pub this_any: (Vec<Note>, Vec<PitchClass>),
+    // The same notes with their octaves, if known.
+    pub pitches: (Vec<Pitch>, Vec<Pitch>),
+    // How long it lasts, in ticks.
+    pub duration: usize,
+    // The same, in milliseconds at the marked tempo, if there is one.
+    pub millis: Option<usize>,
+    // How long its notes are written to last, in ticks.
+    pub sustain: usize,
+    // Whether it damps every string first.
+    pub damp: bool,
+    // What the player asked for at it, if anything.
+    pub directives: Directives,
+    // A pedal diagram printed before it.
+    pub checkpoint: Option<Harp>,
+    // Whether it is a glissando, sweeping every string.
+    pub gliss: bool,
+    // Pitch classes wanted on two strings, for a bisbigliando.
+    pub bisb: Vec<PitchClass>,
 }
 
-impl CandidateBuilder {
-    pub fn new() -> CandidateBuilder {
-        CandidateBuilder {
-            diagram: None,
-            destination: None,
-            spelling: None,
-            pedals: None,
-            cost: None,
+impl BeatInput {
+    // A quarter note of these notes, with nothing else asked of it.
+    pub fn new(this: Vec<Note>, any: Vec<PitchClass>) -> BeatInput {
+        BeatInput {
+            this_any: (this, any),
+            pitches: (vec![], vec![]),
+            duration: QUARTER,
+            millis: None,
+            sustain: QUARTER,
+            damp: false,
+            directives: Directives::default(),
+            checkpoint: None,
+            gliss: false,
+            bisb: vec![],
         }
     }
 
-    pub fn set_diagram(&mut self, diagram: Harp) {
-        self.diagram = Some(diagram);
-    }
-
-    pub fn set_destination(&mut self, destination: Harp) {
-        self.destination = Some(destination);
-    }
-
-    pub fn set_spelling(&mut self, spelling: Vec<Harp>) {
-        self.spelling = Some(spelling);
+    // Where it lies, if every note in it has an octave.
+    // A glissando sweeps every octave, so has no one place.
+    pub fn register(&self) -> Option<Register> {
+        if self.gliss {
+            return None;
+        }
+        let (fixed, free) = &self.pitches;
+        Register::new(fixed, free)
     }
+}
 
-    pub fn set_pedals(&mut self, pedals: Pedals) {
-        self.pedals = Some(pedals);
+impl Default for BeatInput {
+    fn default() -> Self {
+        Self::new(vec![], vec![])
     }
+}
 
-    pub fn set_cost(&mut self, cost: usize) {
-        self.cost = Some(cost);
-    }
+// Changes the player has pinned to a beat, or feet they must keep still,
+// written as {F#}, {!}, {!L} or {!R} before it. Plans must obey them.
+#[derive(
+    Clone, Debug, Default, Eq, Hash, PartialEq, Serialize, Deserialize,
+)]
+#[serde(default)]
+pub struct Directives {
+    pub changes: Vec<Note>,
+    pub hold_left: bool,
+    pub hold_right: bool,
+}
 
-    pub fn refine_spelling(&mut self, input: &MusicInput) {
-        for s in self.spelling.iter_mut() {
-            // For each beat
-            for (i, m) in input.music.iter().enumerate() {
-                // For each pitch
-                for (j, d) in s[i].iter_mut().enumerate() {
-                    if let Some(note) = idx_to_note(j, *d) {
-                        if !m.0.contains(&note)
-                            && !m.1.contains(&note_to_pc(note))
-                        {
-                            *d = None;
-                        }
-                    }
-                }
-            }
+impl Directives {
+    fn holds(&self, foot: Foot) -> bool {
+        match foot {
+            Foot::Left => self.hold_left,
+            Foot::Right => self.hold_right,
         }
     }
 
-    pub fn try_init(self) -> Option<Candidate> {
-        Some(Candidate {
-            diagram: self.diagram?,
-            destination: self.destination?,
-            spelling: self.spelling?,
-            pedals: self.pedals?,
-            cost: self.cost?,
+    // At most one change per foot, and none for a foot that is held.
+    pub fn is_possible(&self) -> bool {
+        [Foot::Left, Foot::Right].iter().all(|foot| {
+            let pinned = self.changes.iter().filter(|n| n.foot() == *foot);
+            match pinned.count() {
+                0 => true,
+                1 => !self.holds(*foot),
+                _ => false,
+            }
         })
     }
-}
 
-impl Default for CandidateBuilder {
-    fn default() -> Self {
-        Self::new()
+    // Whether a foot may make this change, None being no change.
+    pub fn allows(&self, foot: Foot, change: Option<Note>) -> bool {
+        match self.changes.iter().find(|n| n.foot() == foot) {
+            Some(pinned) => change == Some(*pinned),
+            None => !self.holds(foot) || change.is_none(),
+        }
     }
 }
 
@@ -121,4 +153,5 @@ pub struct Candidate {
     // Should be one longer than spelling, last is required changes for goal.
     pub pedals: Pedals,
     pub cost: usize,
+    pub ledger: Ledger,
 }
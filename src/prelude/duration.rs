@@ -0,0 +1,66 @@
+use serde::{Deserialize, Serialize};
+
+// Durations are measured in ticks. A whole note is 4 * QUARTER ticks,
+// and a double-dotted 64th note is still a whole number of ticks.
+pub const QUARTER: usize = 256;
+const WHOLE: usize = 4 * QUARTER;
+const SHORTEST: usize = 64;
+const MAX_DOTS: usize = 2;
+
+// The length of a note value, eg (4, 0) is a quarter, (8, 1) a dotted eighth.
+pub fn note_value(base: usize, dots: usize) -> Option<usize> {
+    if !base.is_power_of_two() || base > SHORTEST || dots > MAX_DOTS {
+        return None;
+    }
+    let plain = WHOLE / base;
+    // Each dot adds half of the previous value.
+    let extra = (1..=dots).map(|d| plain >> d).sum::<usize>();
+    Some(plain + extra)
+}
+
+// Split a duration into tied note values, longest first.
+// Anything shorter than a 64th note is dropped.
+pub fn split_value(ticks: usize) -> Vec<(usize, usize)> {
+    let mut out = vec![];
+    let mut rem = ticks;
+    'outer: while rem >= WHOLE / SHORTEST {
+        let mut base = 1;
+        while base <= SHORTEST {
+            for dots in (0..=MAX_DOTS).rev() {
+                if let Some(v) = note_value(base, dots) {
+                    if v <= rem {
+                        out.push((base, dots));
+                        rem -= v;
+                        continue 'outer;
+                    }
+                }
+            }
+            base *= 2;
+        }
+    }
+    out
+}
+
+// So many beats of unit ticks per minute, eg 4. = 96 is (3 * QUARTER / 2, 96).
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Tempo {
+    pub unit: usize,
+    pub bpm: usize,
+}
+
+// Assumed until the music says otherwise.
+pub const DEFAULT_TEMPO: Tempo = Tempo {
+    unit: QUARTER,
+    bpm: 120,
+};
+
+impl Tempo {
+    pub fn new(unit: usize, bpm: usize) -> Tempo {
+        Tempo { unit, bpm }
+    }
+
+    // How long so many ticks last in real time.
+    pub fn millis(&self, ticks: usize) -> usize {
+        ticks * 60_000 / (self.unit * self.bpm)
+    }
+}
@@ -92,6 +92,19 @@ pub fn update_harp(state: Harp, change: Harp) -> Harp {
     out
 }
 
+// The setting that satisfies both, if they don't disagree on any pedal.
+pub fn meet(a: Harp, b: Harp) -> Option<Harp> {
+    let mut out = a;
+    for i in 0..=6 {
+        match (a[i], b[i]) {
+            (Some(x), Some(y)) if x != y => return None,
+            (None, y) => out[i] = y,
+            _ => (),
+        }
+    }
+    Some(out)
+}
+
 pub fn update_harps(state: Harp, changes: &[Harp]) -> Harp {
     changes.iter().fold(state, |x, y| update_harp(x, *y))
 }
@@ -177,6 +190,25 @@ pub fn num_same(state: Harp) -> usize {
     pitches.len() - pitches.iter().map(|n| note_to_pc(*n)).unique().count()
 }
 
+// Pedals sounding the same pitch as another, eg E# and F.
+pub fn doubled_pairs(state: Harp) -> Vec<(usize, usize)> {
+    let pcs = |i: usize| idx_to_note(i, state[i]).map(note_to_pc);
+    (0..7)
+        .tuple_combinations()
+        .filter(|(i, j)| pcs(*i).is_some() && pcs(*i) == pcs(*j))
+        .collect_vec()
+}
+
+// Pedals whose strings sound out of order, eg E# and Fb.
+pub fn crossed_pairs(state: Harp) -> Vec<(usize, usize)> {
+    [(1, 2), (3, 4)]
+        .into_iter()
+        .filter(|(i, j)| {
+            num_crossed(notes_to_harp(&harp_notes(state, *i..=*j))) == 1
+        })
+        .collect_vec()
+}
+
 // Essentially, ([E, F, G], [E, F#, A]) -> F#
 pub fn get_pedal_changes(state: Harp, target: Harp) -> (Vec<Note>, Vec<Note>) {
     let mut left_shifts = Vec::with_capacity(3);
@@ -1,7 +1,9 @@
 use crate::prelude::*;
+use serde::{Deserialize, Serialize};
 use std::fmt;
+use std::str::FromStr;
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum Name {
     A,
     B,
@@ -26,7 +28,7 @@ impl fmt::Display for Name {
     }
 }
 
-#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
 pub enum Accidental {
     Flat,
     Sharp,
@@ -58,7 +60,9 @@ pub fn pedal_symbol_opt(modifier: Option<Accidental>) -> char {
     }
 }
 
-#[derive(Copy, Clone, Eq, Hash, PartialEq)]
+// Written as a string, such as "Eb" or "E♭".
+#[derive(Copy, Clone, Eq, Hash, PartialEq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub struct Note {
     pub name: Name,
     pub accidental: Accidental,
@@ -76,34 +80,85 @@ impl fmt::Display for Note {
     }
 }
 
+impl FromStr for Note {
+    type Err = String;
+
+    fn from_str(string: &str) -> Result<Note, String> {
+        let mut chars = string.chars();
+        let name = match chars.next() {
+            Some('A') => Name::A,
+            Some('B') => Name::B,
+            Some('C') => Name::C,
+            Some('D') => Name::D,
+            Some('E') => Name::E,
+            Some('F') => Name::F,
+            Some('G') => Name::G,
+            None => {
+                return Err("Empty string given as note name. \
+                    John Cage, is that you?"
+                    .to_string())
+            }
+            Some(x) => return Err(format!("Invalid note name {x}")),
+        };
+        let modifier = match chars.next() {
+            Some('b' | 'f' | '♭') => Flat,
+            Some('n' | '♮') | None => Natural,
+            Some('s' | '#' | '♯') => Sharp,
+            Some(x) => return Err(format!("Invalid modifier {x}")),
+        };
+        if let Some(x) = chars.next() {
+            return Err(format!("Unexpected {x} after note"));
+        }
+        Ok(Note {
+            name,
+            accidental: modifier,
+        })
+    }
+}
+
+impl From<Note> for String {
+    fn from(note: Note) -> String {
+        note.to_string()
+    }
+}
+
+impl TryFrom<String> for Note {
+    type Error = String;
+
+    fn try_from(string: String) -> Result<Note, String> {
+        string.parse()
+    }
+}
+
+// For note literals; use str::parse for anything a user typed.
 pub fn read_note(string: &str) -> Note {
-    let mut chars = string.chars();
-    let name = match chars.next() {
-        Some('A') => Name::A,
-        Some('B') => Name::B,
-        Some('C') => Name::C,
-        Some('D') => Name::D,
-        Some('E') => Name::E,
-        Some('F') => Name::F,
-        Some('G') => Name::G,
-        None => {
-            panic!("Empty string given as note name. John Cage, is that you?")
+    string.parse().unwrap_or_else(|e| panic!("{e}"))
+}
+
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub enum Foot {
+    Left,
+    Right,
+}
+
+impl fmt::Display for Foot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Foot::Left => write!(f, "left"),
+            Foot::Right => write!(f, "right"),
         }
-        Some(x) => panic!("Invalid note name {x}"),
-    };
-    let modifier = match chars.next() {
-        Some('b' | 'f' | '♭') => Flat,
-        Some('n' | '♮') | None => Natural,
-        Some('s' | '#' | '♯') => Sharp,
-        Some(x) => panic!("Invalid modifier {x}"),
-    };
-    Note {
-        name,
-        accidental: modifier,
     }
 }
 
 impl Note {
+    pub fn foot(&self) -> Foot {
+        if self.is_left() {
+            Foot::Left
+        } else {
+            Foot::Right
+        }
+    }
+
     pub fn is_left(&self) -> bool {
         (self.name == Name::D) | (self.name == Name::C) | (self.name == Name::B)
     }
@@ -0,0 +1,136 @@
+use crate::prelude::*;
+
+// A Harp in two bits per pedal, in the same order, D at the bottom.
+// 0 is unset, 1 flat, 2 natural and 3 sharp, so the search can hash,
+// compare and update settings as plain integers.
+#[derive(Copy, Clone, Debug, Default, Eq, Hash, PartialEq)]
+pub struct PackedHarp(u16);
+
+// The low bit of every pedal's pair.
+const LOW_BITS: u16 = 0b01_0101_0101_0101;
+
+// The pedals a foot reaches, as masks over both bits.
+pub const LEFT_PEDALS: u16 = 0b11_1111;
+pub const RIGHT_PEDALS: u16 = 0b11_1111_1100_0000;
+pub const ALL_PEDALS: u16 = LEFT_PEDALS | RIGHT_PEDALS;
+
+const fn field(idx: usize, accidental: u16) -> u16 {
+    accidental << (2 * idx)
+}
+
+const fn both(i: usize, a: u16, j: usize, b: u16) -> (u16, u16) {
+    (field(i, 3) | field(j, 3), field(i, a) | field(j, b))
+}
+
+const FLAT: u16 = 1;
+const NATURAL: u16 = 2;
+const SHARP: u16 = 3;
+
+// Pairs of pedals sounding the same pitch, eg E# and F, as (mask, value).
+const DOUBLES: [(u16, u16); 9] = [
+    both(1, SHARP, 0, FLAT),    // C# Db
+    both(0, SHARP, 3, FLAT),    // D# Eb
+    both(3, SHARP, 4, NATURAL), // E# F
+    both(3, NATURAL, 4, FLAT),  // E Fb
+    both(4, SHARP, 5, FLAT),    // F# Gb
+    both(5, SHARP, 6, FLAT),    // G# Ab
+    both(6, SHARP, 2, FLAT),    // A# Bb
+    both(2, SHARP, 1, NATURAL), // B# C
+    both(2, NATURAL, 1, FLAT),  // B Cb
+];
+
+// Pairs of pedals whose strings sound out of order, eg E# and Fb.
+const CROSSES: [(u16, u16); 2] =
+    [both(1, FLAT, 2, SHARP), both(3, SHARP, 4, FLAT)];
+
+fn to_bits(accidental: Option<Accidental>) -> u16 {
+    match accidental {
+        None => 0,
+        Some(Flat) => FLAT,
+        Some(Natural) => NATURAL,
+        Some(Sharp) => SHARP,
+    }
+}
+
+fn from_bits(bits: u16) -> Option<Accidental> {
+    match bits & 3 {
+        FLAT => Some(Flat),
+        NATURAL => Some(Natural),
+        SHARP => Some(Sharp),
+        _ => None,
+    }
+}
+
+impl PackedHarp {
+    pub fn get(self, idx: usize) -> Option<Accidental> {
+        from_bits(self.0 >> (2 * idx))
+    }
+
+    pub fn set(&mut self, note: Note) {
+        let idx = name_to_usize(note.name);
+        self.0 = (self.0 & !field(idx, 3))
+            | field(idx, to_bits(Some(note.accidental)));
+    }
+
+    // Both bits of every pedal that is set.
+    fn set_mask(self) -> u16 {
+        ((self.0 | (self.0 >> 1)) & LOW_BITS) * 3
+    }
+
+    // As update_harp.
+    pub fn update(self, change: PackedHarp) -> PackedHarp {
+        PackedHarp((self.0 & !change.set_mask()) | change.0)
+    }
+
+    // Pedals, within mask, set in both but to different things.
+    fn changed(self, finish: PackedHarp, mask: u16) -> u16 {
+        let differ = PackedHarp(self.0 ^ finish.0).set_mask();
+        self.set_mask() & finish.set_mask() & differ & mask
+    }
+
+    // As harp_changes, over the pedals in mask.
+    pub fn changes(self, finish: PackedHarp, mask: u16) -> Vec<Note> {
+        let changed = self.changed(finish, mask);
+        (0..7)
+            .filter(|i| changed & field(*i, 3) != 0)
+            .filter_map(|i| idx_to_note(i, finish.get(i)))
+            .collect()
+    }
+
+    // As num_changes, over the pedals in mask.
+    pub fn num_changes(self, finish: PackedHarp, mask: u16) -> usize {
+        (self.changed(finish, mask) & LOW_BITS).count_ones() as usize
+    }
+
+    // As num_same.
+    pub fn num_same(self) -> usize {
+        DOUBLES
+            .iter()
+            .filter(|(mask, value)| self.0 & mask == *value)
+            .count()
+    }
+
+    // As num_crossed.
+    pub fn num_crossed(self) -> usize {
+        CROSSES
+            .iter()
+            .filter(|(mask, value)| self.0 & mask == *value)
+            .count()
+    }
+}
+
+impl From<Harp> for PackedHarp {
+    fn from(harp: Harp) -> PackedHarp {
+        PackedHarp(
+            harp.iter()
+                .enumerate()
+                .fold(0, |out, (i, a)| out | field(i, to_bits(*a))),
+        )
+    }
+}
+
+impl From<PackedHarp> for Harp {
+    fn from(packed: PackedHarp) -> Harp {
+        std::array::from_fn(|i| packed.get(i))
+    }
+}
@@ -0,0 +1,157 @@
+use crate::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// Strings are numbered by scale degree from C0, so C1 is 7.
+// A concert harp has 47 of them, from C1 to G7.
+pub const LOWEST_STRING: usize = 7;
+pub const HIGHEST_STRING: usize = 53;
+
+fn degree(name: Name) -> usize {
+    match name {
+        Name::C => 0,
+        Name::D => 1,
+        Name::E => 2,
+        Name::F => 3,
+        Name::G => 4,
+        Name::A => 5,
+        Name::B => 6,
+    }
+}
+
+// Semitones above the C of the same octave, so Cb is -1 and B# is 12.
+fn semitone(note: Note) -> isize {
+    let natural = match note.name {
+        Name::C => 0,
+        Name::D => 2,
+        Name::E => 4,
+        Name::F => 5,
+        Name::G => 7,
+        Name::A => 9,
+        Name::B => 11,
+    };
+    match note.accidental {
+        Flat => natural - 1,
+        Natural => natural,
+        Sharp => natural + 1,
+    }
+}
+
+// A note, and maybe the octave of the string it is played on.
+// Octaves are as in scientific pitch notation, so C4 is middle C
+// and B#3 sounds the same as C4.
+#[derive(Copy, Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+pub struct Pitch {
+    pub note: Note,
+    pub octave: Option<u8>,
+}
+
+impl fmt::Display for Pitch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.octave {
+            Some(o) => write!(f, "{}{o}", self.note),
+            None => write!(f, "{}", self.note),
+        }
+    }
+}
+
+impl Pitch {
+    pub fn new(note: Note, octave: Option<u8>) -> Pitch {
+        Pitch { note, octave }
+    }
+
+    // Which string plays it, if we know the octave.
+    pub fn string(&self) -> Option<usize> {
+        self.octave.map(|o| o as usize * 7 + degree(self.note.name))
+    }
+
+    // Notes without an octave could be anywhere, so are allowed.
+    pub fn on_harp(&self) -> bool {
+        self.string()
+            .is_none_or(|s| (LOWEST_STRING..=HIGHEST_STRING).contains(&s))
+    }
+
+    fn semitones(&self) -> Option<isize> {
+        self.octave.map(|o| o as isize * 12 + semitone(self.note))
+    }
+
+    // The same sound on another string, eg E#4 as F4 or B#3 as C4.
+    pub fn respell(&self, note: Note) -> Pitch {
+        let octave = self.semitones().and_then(|s| {
+            u8::try_from((s - semitone(note)).div_euclid(12)).ok()
+        });
+        Pitch { note, octave }
+    }
+}
+
+// Where a beat's notes lie, once every one of them has an octave.
+// Fixed pitches keep their string, free ones go on whichever sounds them.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Register {
+    pub fixed: Vec<Pitch>,
+    pub free: Vec<Pitch>,
+}
+
+impl Register {
+    // A rest tells us nothing, nor does a chord missing any octave.
+    pub fn new(fixed: &[Pitch], free: &[Pitch]) -> Option<Register> {
+        let known = |p: &Pitch| p.octave.is_some();
+        if (fixed.is_empty() && free.is_empty())
+            || !fixed.iter().all(known)
+            || !free.iter().all(known)
+        {
+            return None;
+        }
+        Some(Register {
+            fixed: fixed.to_vec(),
+            free: free.to_vec(),
+        })
+    }
+
+    // The strings each pedal's notes are played on, given the pedals.
+    // A free pitch that two strings sound could go on either,
+    // and so never needs to be near the other one.
+    pub fn strings(&self, pedals: Harp) -> [Vec<usize>; 7] {
+        let mut out: [Vec<usize>; 7] = Default::default();
+        for p in &self.fixed {
+            out[name_to_usize(p.note.name)].extend(p.string());
+        }
+        for p in &self.free {
+            let pc = note_to_pc(p.note);
+            let mut on = harp_to_notes(pedals)
+                .into_iter()
+                .filter(|n| note_to_pc(*n) == pc);
+            if let (Some(n), None) = (on.next(), on.next()) {
+                out[name_to_usize(n.name)].extend(p.respell(n).string());
+            }
+        }
+        out
+    }
+}
+
+// The pitches a beat plays, spelled as the pedals have them.
+// Free pitches keep their written spelling where they can.
+pub fn spell_pitches(
+    pedals: Harp,
+    fixed: &[Pitch],
+    free: &[Pitch],
+) -> Vec<Pitch> {
+    let notes = harp_to_notes(pedals);
+    let mut out = fixed.to_vec();
+    for p in free {
+        let pc = note_to_pc(p.note);
+        let spelled = if notes.contains(&p.note) {
+            *p
+        } else {
+            match notes.iter().find(|n| note_to_pc(**n) == pc) {
+                Some(n) => p.respell(*n),
+                None => *p,
+            }
+        };
+        if !out.contains(&spelled) {
+            out.push(spelled);
+        }
+    }
+    out.sort_by_key(|p| p.semitones());
+    out
+}
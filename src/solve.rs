@@ -1,42 +1,105 @@
 #![allow(dead_code)]
 use itertools::Itertools;
 
-use crate::assign::assign;
-use crate::astar::find_solutions;
+use crate::assign::{
+    assign_gliss, explain, explain_gliss, AssignCache, Conflict,
+};
+use crate::astar::{find_k_solutions, find_solutions, Beat};
+use crate::config::SolverConfig;
 use crate::prelude::*;
 use crate::util::unwrap_or_idx;
 
-pub fn get_spellings(
+// The possible spellings of each chord, or why some can't be played.
+pub fn get_chords(
     input: &MusicInput,
-) -> Result<(Vec<Vec<Harp>>, usize), Vec<usize>> {
-    let start = input.diagram;
-    let end = input.goal;
+) -> Result<Vec<Beat>, Vec<(usize, Conflict)>> {
+    let mut cache = AssignCache::default();
     let mid = input
         .music
         .iter()
-        .map(|(preset, other)| assign(preset, other))
+        .map(|beat| {
+            let (preset, other) = &beat.this_any;
+            if beat.gliss {
+                assign_gliss(preset, other)
+            } else {
+                cache.assign(preset, other)
+            }
+        })
         .collect::<Vec<Option<Vec<Harp>>>>();
-    let chords = unwrap_or_idx(&mid)?;
-    let (solutions, cost) = find_solutions(start, &chords, end);
-    Ok((
+    let chords = unwrap_or_idx(&mid).map_err(|idx| {
+        idx.into_iter()
+            .filter_map(|i| {
+                let beat = &input.music[i];
+                let (preset, other) = &beat.this_any;
+                let conflict = if beat.gliss {
+                    explain_gliss(preset, other)
+                } else {
+                    explain(preset, other)
+                };
+                conflict.map(|c| (i, c))
+            })
+            .collect_vec()
+    })?;
+    Ok(chords
+        .into_iter()
+        .zip(&input.music)
+        .map(|(targets, beat)| {
+            Beat::new(
+                targets,
+                beat.duration,
+                beat.millis,
+                beat.register(),
+                beat.sustain,
+                beat.damp,
+                beat.directives.clone(),
+            )
+            .with_checkpoint(beat.checkpoint)
+            .with_bisb(beat.bisb.clone())
+            .with_gliss(beat.gliss)
+        })
+        .collect_vec())
+}
+
+pub fn get_spellings(
+    input: &MusicInput,
+    chords: &[Beat],
+    config: &SolverConfig,
+) -> (Vec<Vec<Harp>>, usize) {
+    let (solutions, cost) =
+        find_solutions(input.diagram, chords, input.goal, config);
+    (
         solutions
             .into_iter()
-            .map(|v| v.into_iter().map(|a| a.pedals).collect_vec())
+            .map(|v| v.into_iter().map(|a| a.harp()).collect_vec())
             .collect_vec(),
         cost,
-    ))
+    )
+}
+
+// Up to k distinct spellings, each with its own cost, cheapest first.
+pub fn get_k_spellings(
+    input: &MusicInput,
+    chords: &[Beat],
+    config: &SolverConfig,
+    k: usize,
+) -> Vec<(Vec<Harp>, usize)> {
+    find_k_solutions(input.diagram, chords, input.goal, config, k)
+        .into_iter()
+        .map(|(v, cost)| (v.into_iter().map(|a| a.harp()).collect_vec(), cost))
+        .collect_vec()
 }
 
 // result is one longer than spelling, since it includes
 // changes left over to get to target state.
-pub fn get_pedal_changes(input: &CandidateBuilder) -> Vec<Vec<Note>> {
-    let mut with_diagram =
-        Vec::with_capacity(input.spelling.as_ref().unwrap().len() + 2);
-    with_diagram.push(input.diagram.unwrap());
-    for s in input.spelling.as_ref().unwrap() {
-        with_diagram.push(*s);
-    }
-    with_diagram.push(input.destination.unwrap());
+pub fn get_pedal_changes(
+    diagram: Harp,
+    spelling: &[Harp],
+    destination: Harp,
+) -> Vec<Vec<Note>> {
+    let mut with_diagram = Vec::with_capacity(spelling.len() + 2);
+    with_diagram.push(diagram);
+    with_diagram.extend_from_slice(spelling);
+    with_diagram.push(destination);
     unset_seen(&with_diagram)
         .iter()
         .map(|h| harp_to_notes(*h))
@@ -0,0 +1,188 @@
+use crate::assign::Conflict;
+use crate::config::SolverConfig;
+use crate::cost::{ledger_total, plan_ledger, CostKind, Ledger};
+use crate::prelude::*;
+use crate::solve::get_chords;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+// What can go wrong with a hand-written plan at one beat.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum Problem {
+    // Notes the pedals don't give at all.
+    Missing(Vec<Note>),
+    // Strings a glissando sweeps that aren't in its chord.
+    OffChord(Vec<Note>),
+    // The pedals disagree with a diagram printed here, or with the goal.
+    Diagram(Harp),
+    // More than one change for a foot at once.
+    TwoChanges(Foot, Vec<Note>),
+    Doubled(Vec<Note>),
+    Crossed(Vec<Note>),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Missing(notes) => {
+                write!(f, "{} can't be played", notes.iter().join(", "))
+            }
+            Problem::OffChord(notes) => write!(
+                f,
+                "the glissando sounds {}, outside its chord",
+                notes.iter().join(", ")
+            ),
+            Problem::Diagram(harp) => {
+                write!(f, "the pedals don't match {}", pedal_diagram(*harp))
+            }
+            Problem::TwoChanges(foot, notes) => write!(
+                f,
+                "the {foot} foot changes {} at once",
+                notes.iter().join(" and ")
+            ),
+            Problem::Doubled(notes) => {
+                write!(f, "doubled strings {}", notes.iter().join(" and "))
+            }
+            Problem::Crossed(notes) => {
+                write!(f, "crossed strings {}", notes.iter().join(" and "))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Verified {
+    // Beat indices, or the music's length for the goal.
+    pub problems: Vec<(usize, Problem)>,
+    pub cost: usize,
+    pub breakdown: Ledger,
+}
+
+impl Verified {
+    // Doubled and crossed strings are costly, the rest can't be played.
+    pub fn is_playable(&self) -> bool {
+        self.problems.iter().all(|(_, p)| {
+            matches!(p, Problem::Doubled(_) | Problem::Crossed(_))
+        })
+    }
+}
+
+// Whether the pedals give every note the spelling asks for.
+fn agrees(pedals: Harp, target: Harp) -> bool {
+    meet(pedals, target) == Some(pedals)
+}
+
+// Why none of a beat's spellings fit the pedals.
+fn why_not(input: &MusicInput, i: usize, pedals: Harp) -> Problem {
+    let beat = &input.music[i];
+    let (fixed, free) = &beat.this_any;
+    let sounds = |pc: PitchClass| {
+        pc_to_notes(pc)
+            .into_iter()
+            .any(|n| pedals[name_to_usize(n.name)] == Some(n.accidental))
+    };
+    let written = |pc: PitchClass| {
+        beat.pitches
+            .1
+            .iter()
+            .find(|p| note_to_pc(p.note) == pc)
+            .map_or_else(|| pc_to_note(pc), |p| p.note)
+    };
+    let missing = fixed
+        .iter()
+        .filter(|n| pedals[name_to_usize(n.name)] != Some(n.accidental))
+        .copied()
+        .chain(
+            free.iter()
+                .filter(|pc| !sounds(**pc))
+                .map(|pc| written(*pc)),
+        )
+        .collect_vec();
+    if !missing.is_empty() {
+        return Problem::Missing(missing);
+    }
+    if beat.gliss {
+        let chord = fixed.iter().map(|n| note_to_pc(*n)).chain(free.clone());
+        let chord = chord.collect_vec();
+        let off = harp_to_notes(pedals)
+            .into_iter()
+            .filter(|n| !chord.contains(&note_to_pc(*n)))
+            .collect_vec();
+        if !off.is_empty() {
+            return Problem::OffChord(off);
+        }
+    }
+    Problem::Diagram(beat.checkpoint.unwrap_or(pedals))
+}
+
+// The music without the changes written in it, for the solver's best to
+// compare with when they are the plan being checked. Held feet still hold.
+pub fn without_written_changes(input: &MusicInput) -> MusicInput {
+    let mut out = input.clone();
+    for beat in out.music.iter_mut() {
+        beat.directives.changes.clear();
+    }
+    out
+}
+
+// Plays a plan through, as given, rather than searching for one.
+// Pedals are the changes before each beat, then optionally those
+// for the goal, which are worked out if left off.
+pub fn verify_plan(
+    input: &MusicInput,
+    diagram: Harp,
+    pedals: &Pedals,
+    config: &SolverConfig,
+) -> Result<Verified, Vec<(usize, Conflict)>> {
+    let chords = get_chords(input)?;
+    let mut problems = vec![];
+    let mut state = diagram;
+    let mut targets = Vec::with_capacity(chords.len() + 1);
+    for (i, beat) in chords.iter().enumerate() {
+        let changes = pedals.get(i).cloned().unwrap_or_default();
+        for foot in [Foot::Left, Foot::Right] {
+            let notes = changes.iter().filter(|n| n.foot() == foot).copied();
+            let notes = notes.collect_vec();
+            if notes.len() > 1 {
+                problems.push((i, Problem::TwoChanges(foot, notes)));
+            }
+        }
+        state = update_harp_notes(state, &[changes]);
+        let target = beat.targets.iter().find(|t| agrees(state, **t));
+        match target.filter(|_| beat.allows(state)) {
+            Some(t) => targets.push(*t),
+            None => {
+                problems.push((i, why_not(input, i, state)));
+                targets.push([None; 7]);
+            }
+        }
+    }
+    let mut pedals = pedals.clone();
+    if pedals.len() < chords.len() {
+        pedals.resize(chords.len(), vec![]);
+    }
+    if pedals.len() == chords.len() {
+        pedals.push(harp_changes(state, input.goal, 0..=6));
+    }
+    let end = update_harp_notes(state, &pedals[chords.len()..]);
+    if !agrees(end, input.goal) {
+        problems.push((chords.len(), Problem::Diagram(input.goal)));
+    }
+    targets.push(input.goal);
+    let breakdown = plan_ledger(diagram, &targets, &chords, &pedals, config);
+    for item in &breakdown {
+        let problem = match item.kind {
+            CostKind::DoubledString => Problem::Doubled(item.notes.clone()),
+            CostKind::CrossedString => Problem::Crossed(item.notes.clone()),
+            _ => continue,
+        };
+        problems.push((item.beat, problem));
+    }
+    problems.sort_by_key(|(i, _)| *i);
+    Ok(Verified {
+        problems,
+        cost: ledger_total(&breakdown),
+        breakdown,
+    })
+}
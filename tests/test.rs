@@ -1,102 +1,897 @@
+use clap::Parser;
 use harp_pedal_solver::assign::*;
-use harp_pedal_solver::base::*;
-use harp_pedal_solver::shift::*;
+use harp_pedal_solver::astar::*;
+use harp_pedal_solver::candidate::*;
+use harp_pedal_solver::cli::*;
+use harp_pedal_solver::config::*;
+use harp_pedal_solver::cost::*;
+use harp_pedal_solver::json::*;
+use harp_pedal_solver::lever::*;
+use harp_pedal_solver::lilypond::*;
+use harp_pedal_solver::musicxml::*;
+use harp_pedal_solver::parse::*;
+use harp_pedal_solver::prelude::*;
+use harp_pedal_solver::solve::*;
 use harp_pedal_solver::util::*;
-use trees::*;
+use harp_pedal_solver::verify::*;
+use itertools::Itertools;
+use std::path::PathBuf;
 
-#[test]
-fn unravel_correctly() {
-    let mut forest = Forest::<usize>::new();
-    forest.push_back(tr(0) / tr(1));
-    forest.push_back(tr(2) / tr(3) / (tr(4) / tr(5) / tr(6)));
-    assert_eq!(
-        unravel_paths(forest),
-        vec![vec![1, 0], vec![3, 2], vec![5, 4, 2], vec![6, 4, 2]]
-    );
+fn notes(v: &[&str]) -> Vec<Note> {
+    v.iter().map(|n| read_note(n)).collect()
+}
+
+fn pc(n: &str) -> PitchClass {
+    note_to_pc(read_note(n))
+}
+
+fn pcs(v: &[&str]) -> Vec<PitchClass> {
+    v.iter().map(|n| pc(n)).collect()
+}
+
+// A quarter note of these notes, spelled as written.
+fn this(v: &[&str]) -> BeatInput {
+    BeatInput::new(notes(v), vec![])
+}
+
+// The same, spelled however suits the pedals.
+fn any(v: &[&str]) -> BeatInput {
+    BeatInput::new(vec![], pcs(v))
+}
+
+// The best plans for the music, by the default config.
+fn solve(input: &MusicInput) -> Vec<Candidate> {
+    find_candidates(input, &SolverConfig::default()).unwrap()
+}
+
+// The same, for .hrp text.
+fn solve_hrp(s: &str) -> Vec<Candidate> {
+    solve(&MusicInput::from(&parse(s).unwrap()))
+}
+
+// One thing about each beat the parser read, measure by measure.
+fn per_beat<T>(parsed: &Parsed, f: impl Fn(&BeatInput) -> T) -> Vec<Vec<T>> {
+    parsed
+        .music
+        .iter()
+        .map(|m| m.iter().map(&f).collect_vec())
+        .collect_vec()
 }
 
 #[test]
 fn can_assign_empty() {
-    assert!(can_assign(&[]));
+    assert!(assign(&[], &[]).is_some());
 }
 
 #[test]
 fn can_assign_nonempty() {
-    assert!(can_assign(&[0, 3, 5, 7, 9]));
+    assert!(assign(&[], &[0, 3, 5, 7, 9]).is_some());
 }
 
 #[test]
 fn cant_assign() {
-    assert!(!can_assign(&[11, 0, 1]));
+    assert!(assign(&[], &[11, 0, 1]).is_none());
 }
 
 #[test]
 fn correct_num_assignations() {
-    assert_eq!(10, assign(&[0, 3, 5, 7, 9]).len());
+    assert_eq!(10, assign(&[], &[0, 3, 5, 7, 9]).unwrap().len());
 }
 
 #[test]
-fn chain_pushes_correctly() {
-    let music: Vec<Vec<Note>> = vec![vec!["F", "G"], vec!["A"], vec!["Gb"]]
-        .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let pedals: Vec<Vec<Note>> = vec![vec!["F", "G"], vec!["A"], vec![]]
-        .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let input: Vec<(Vec<Note>, Vec<Note>)> =
-        music.into_iter().zip(pedals).collect();
-    let shifted = unravel_paths(change_builder(&input, &[]));
-    assert_eq!(3, shifted.len());
+fn assigns_lazily_and_once() {
+    let first = assignments(&[], &[0, 3, 5, 7, 9]).next().unwrap();
+    assert_eq!(
+        Some(first),
+        assign(&[], &[0, 3, 5, 7, 9]).unwrap().first().copied()
+    );
+    assert_eq!(assignments(&[], &[11, 0, 1]).next(), None);
+    let preset = [read_note("C#"), read_note("F")];
+    let mut cache = AssignCache::default();
+    let once = cache.assign(&preset, &[7, 1, 7]);
+    assert_eq!(once, assign(&preset, &[7, 1]));
+    let swapped = [read_note("F"), read_note("C#")];
+    assert_eq!(cache.assign(&swapped, &[1, 7]), once);
+}
+
+// Runs under the test harness's own argv, so must not read the command line.
+#[test]
+fn solves_with_explicit_config() {
+    let input = MusicInput {
+        music: vec![any(&["F", "G"]), any(&["A"]), this(&["Gb"])],
+        ..Default::default()
+    };
+    let candidates = solve(&input);
+    assert!(!candidates.is_empty());
+    assert_eq!(candidates[0].pedals.len(), input.music.len() + 1);
+    assert!(candidates[0].pedals.iter().flatten().any(|n| *n
+        == Note {
+            name: Name::G,
+            accidental: Flat
+        }));
 }
 
 #[test]
-fn abandon_when_too_many() {
-    let music: Vec<Vec<Note>> = vec![vec!["F", "G"], vec!["A"], vec!["Gb"]]
+fn reads_musicxml() {
+    let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<score-partwise version="4.0">
+  <part-list><score-part id="P1"><part-name>Harp</part-name></score-part></part-list>
+  <part id="P1">
+    <measure number="1">
+      <direction><direction-type><harp-pedals>
+        <pedal-tuning><pedal-step>D</pedal-step><pedal-alter>0</pedal-alter></pedal-tuning>
+        <pedal-tuning><pedal-step>E</pedal-step><pedal-alter>-1</pedal-alter></pedal-tuning>
+      </harp-pedals></direction-type></direction>
+      <note><pitch><step>A</step><octave>4</octave></pitch><duration>1</duration></note>
+      <note><chord/><pitch><step>C</step><octave>5</octave></pitch><duration>1</duration></note>
+      <note><pitch><step>E</step><alter>-1</alter><octave>5</octave></pitch><duration>1</duration></note>
+      <backup><duration>2</duration></backup>
+      <note><pitch><step>F</step><alter>1</alter><octave>3</octave></pitch><duration>2</duration></note>
+    </measure>
+    <measure number="2">
+      <direction><direction-type><harp-pedals>
+        <pedal-tuning><pedal-step>F</pedal-step><pedal-alter>1</pedal-alter></pedal-tuning>
+      </harp-pedals></direction-type></direction>
+      <note><rest/><duration>2</duration></note>
+      <direction><direction-type><harp-pedals>
+        <pedal-tuning><pedal-step>G</pedal-step><pedal-alter>-1</pedal-alter></pedal-tuning>
+      </harp-pedals></direction-type></direction>
+    </measure>
+  </part>
+</score-partwise>"#;
+    let parsed = parse_musicxml(xml, false).unwrap();
+    let mut start = [None; 7];
+    set_pedal(&mut start, read_note("D"));
+    set_pedal(&mut start, read_note("Eb"));
+    assert_eq!(parsed.start, Some(start));
+    assert_eq!(
+        per_beat(&parsed, |b| b.this_any.clone()),
+        vec![
+            vec![
+                (vec![], vec![pc("A"), pc("C"), pc("F#")]),
+                (vec![], vec![pc("Eb")])
+            ],
+            vec![(vec![], vec![])]
+        ]
+    );
+    // Later diagrams are checkpoints, or the end after the last note.
+    let diagram = |n| Some(notes_to_harp(&[read_note(n)]));
+    assert_eq!(
+        per_beat(&parsed, |b| b.checkpoint),
+        vec![vec![None, None], vec![diagram("F#")]]
+    );
+    assert_eq!(parsed.end, diagram("Gb"));
+    let kept = parse_musicxml(xml, true).unwrap();
+    assert_eq!(kept.music[0][1].this_any, (notes(&["Eb"]), vec![]));
+}
+
+#[test]
+fn musicxml_round_trip() {
+    let at = |octave: u8, v: &[&str]| {
+        v.iter()
+            .map(|n| Pitch::new(read_note(n), Some(octave)))
+            .collect::<Vec<_>>()
+    };
+    let treble = vec![
+        vec![(at(3, &["A", "C", "Eb"]), QUARTER), (vec![], QUARTER / 2)],
+        // Five sixteenths have to be written as tied notes.
+        vec![
+            (at(5, &["F#"]), 5 * QUARTER / 4),
+            (at(2, &["Fn", "Gb"]), QUARTER),
+        ],
+    ];
+    let start = notes_to_harp(&notes(&["D", "C", "B", "Eb", "F", "G", "A"]));
+    let end = notes_to_harp(&notes(&["D", "C", "B", "Eb", "F", "Gb", "A"]));
+    let changes = vec![vec![], vec![], vec![read_note("F#")], vec![], vec![]];
+    let xml = make_xml_file(treble.clone(), start, end, &changes);
+    assert!(xml.contains("<beats>3</beats><beat-type>8</beat-type>"));
+    assert!(xml.contains("<beats>9</beats><beat-type>16</beat-type>"));
+    let parsed = parse_musicxml(&xml, true).unwrap();
+    assert_eq!(parsed.start, Some(start));
+    let expected = treble
+        .iter()
+        .map(|m| {
+            m.iter()
+                .map(|(b, _)| (b.iter().map(|p| p.note).collect(), vec![]))
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(per_beat(&parsed, |b| b.this_any.clone()), expected);
+    let pitches = treble
         .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let pedals: Vec<Vec<Note>> = vec![vec!["F", "G"], vec!["A"], vec!["C"]]
+        .map(|m| m.iter().map(|(b, _)| (b.clone(), vec![])).collect())
+        .collect::<Vec<Vec<_>>>();
+    assert_eq!(per_beat(&parsed, |b| b.pitches.clone()), pitches);
+    let durations = treble
         .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let input: Vec<(Vec<Note>, Vec<Note>)> =
-        music.into_iter().zip(pedals).collect();
-    let shifted = unravel_paths(change_builder(&input, &[]));
-    assert_eq!(0, shifted.len());
+        .map(|m| m.iter().map(|(_, d)| *d).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(per_beat(&parsed, |b| b.duration), durations);
+}
+
+#[test]
+fn parse_error_position() {
+    let source = "~~~|~~~~ $ [x\n[a b] $ [x\n| [c *q] |\n";
+    let e = parse(source).err().unwrap();
+    assert_eq!((e.line, e.column), (3, 7));
+    assert_eq!(e.text, "q");
+    assert_eq!(e.expected, "a note after '*'");
+    assert!(e.annotate(source).contains("3 | | [c *q] |\n"));
+}
+
+#[test]
+fn parse_error_in_diagram() {
+    let e = parse("~~v~|~~~~\n[a]").err().unwrap();
+    assert_eq!((e.line, e.column), (1, 4));
+    assert_eq!(e.expected, "'|' after three pedal settings");
 }
 
 #[test]
-fn abandon_when_impossible() {
-    let music: Vec<Vec<Note>> =
-        vec![vec!["F", "G"], vec!["F#", "G#"], vec!["Gb"]]
+fn bad_note_is_an_error() {
+    assert!("H".parse::<Note>().is_err());
+    assert!("Bx".parse::<Note>().is_err());
+    assert_eq!("Bb".parse::<Note>(), Ok(read_note("B♭")));
+}
+
+#[test]
+fn explains_impossible_chord() {
+    let conflict =
+        explain(&[], &[pc("G"), pc("G#"), pc("A"), pc("C")]).unwrap();
+    assert_eq!(conflict.pitches, vec![pc("G"), pc("G#"), pc("A")]);
+    assert_eq!(conflict.strings, vec![Name::G, Name::A]);
+    assert!(explain(&[], &[pc("G"), pc("A")]).is_none());
+}
+
+#[test]
+fn suggests_respelling() {
+    let fixed = [read_note("Gb"), read_note("G")];
+    assert!(assign(&fixed, &[]).is_none());
+    let conflict = explain(&fixed, &[]).unwrap();
+    assert_eq!(conflict.respell, vec![read_note("Gb")]);
+}
+
+#[test]
+fn locates_beats() {
+    assert_eq!(measure_and_beat(&[2, 3], 0), Some((1, 1)));
+    assert_eq!(measure_and_beat(&[2, 3], 4), Some((2, 3)));
+    assert_eq!(measure_and_beat(&[2, 3], 5), None);
+}
+
+#[test]
+fn k_best_are_distinct_and_sorted() {
+    let input = MusicInput {
+        diagram: notes_to_harp(&notes(&["D", "C"])),
+        music: vec![
+            any(&["F", "G", "B"]),
+            any(&["F#", "A"]),
+            any(&["Gb", "Bb"]),
+        ],
+        goal: [None; 7],
+    };
+    let config = SolverConfig::default();
+    let best = find_candidates(&input, &config).unwrap();
+    let options = find_k_candidates(&input, &config, 4).unwrap();
+    assert_eq!(options.len(), 4);
+    assert_eq!(options[0].cost, best[0].cost);
+    assert!(options.windows(2).all(|w| w[0].cost <= w[1].cost));
+    // Not just the same changes made in another order.
+    let pedal_changes = |c: &Candidate| {
+        let changes = c.pedals.concat().into_iter();
+        (c.diagram, changes.into_group_map_by(|n| n.name))
+    };
+    for (i, a) in options.iter().enumerate() {
+        for b in options[i + 1..].iter() {
+            assert_ne!(pedal_changes(a), pedal_changes(b));
+        }
+    }
+}
+
+#[test]
+fn ledger_matches_cost() {
+    let input = MusicInput {
+        diagram: notes_to_harp(&notes(&["D", "C"])),
+        music: vec![
+            any(&["F", "G", "B"]),
+            any(&["F#", "A", "Cb"]),
+            any(&["Gb", "Bb", "E#"]),
+            any(&["D#", "G"]),
+        ],
+        goal: [None; 7],
+    };
+    let config = SolverConfig::default();
+    let chords = get_chords(&input).unwrap();
+    for (path, cost) in
+        find_k_solutions(input.diagram, &chords, input.goal, &config, 3)
+    {
+        let items: usize = path
+            .windows(2)
+            .map(|w| ledger_total(&cost_items(w[0], w[1], None, &config)))
+            .sum();
+        let direct: usize = path
+            .windows(2)
+            .map(|w| astar_cost(w[0], w[1], None, &config))
+            .sum();
+        assert_eq!(items, direct);
+        assert!(items <= cost);
+    }
+    for c in find_k_candidates(&input, &config, 3).unwrap() {
+        assert_eq!(ledger_total(&c.ledger) / input.music.len(), c.cost);
+    }
+}
+
+#[test]
+fn reads_durations() {
+    let parsed = parse("[a]8. [b]16 [c] | [d]2 [e f]4.. [r]").unwrap();
+    assert_eq!(
+        per_beat(&parsed, |b| b.duration),
+        vec![
+            vec![3 * QUARTER / 4, QUARTER / 4, QUARTER / 4],
+            vec![2 * QUARTER, 7 * QUARTER / 4, 7 * QUARTER / 4]
+        ]
+    );
+    let e = parse("[a]3").err().unwrap();
+    assert_eq!((e.line, e.column), (1, 4));
+    assert_eq!(split_value(5 * QUARTER / 4), vec![(4, 0), (16, 0)]);
+}
+
+#[test]
+fn writes_rhythms_in_time() {
+    let a = || {
+        vec![Pitch {
+            note: read_note("A"),
+            octave: None,
+        }]
+    };
+    let treble = vec![
+        vec![
+            (a(), 3 * QUARTER / 2),
+            (vec![], QUARTER / 2),
+            (a(), QUARTER),
+        ],
+        vec![(a(), 3 * QUARTER / 2), (a(), 3 * QUARTER / 2)],
+        vec![(a(), 5 * QUARTER / 2)],
+    ];
+    let pedals = vec![vec![]; 5];
+    let ly = make_ly_file_(treble, [None; 7], [None; 7], &pedals, 0);
+    assert!(!ly.contains("cadenza"));
+    assert!(ly.contains("\\time 3/4 a4. r8 a4"));
+    assert!(ly.contains("\na4. a4.\n"));
+    assert!(ly.contains("\\time 5/8 a2~ a8"));
+}
+
+#[test]
+fn costs_scale_with_time() {
+    let music = [this(&["F"]), this(&["F#"]), this(&["F"]), this(&["F#"])];
+    let cost = |duration: Option<usize>| {
+        let music = music
             .iter()
-            .map(|v| v.iter().map(|n| read_note(n)).collect())
-            .collect();
-    let pedals: Vec<Vec<Note>> = vec![vec!["F", "G"], vec![], vec![]]
-        .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let input: Vec<(Vec<Note>, Vec<Note>)> =
-        music.into_iter().zip(pedals).collect();
-    let shifted = unravel_paths(change_builder(&input, &[]));
-    assert_eq!(0, shifted.len());
+            .map(|b| match duration {
+                Some(d) => BeatInput {
+                    duration: d,
+                    sustain: d,
+                    ..b.clone()
+                },
+                None => b.clone(),
+            })
+            .collect_vec();
+        let input = MusicInput {
+            diagram: [None; 7],
+            music,
+            goal: [None; 7],
+        };
+        let c = find_k_candidates(&input, &SolverConfig::default(), 1).unwrap();
+        ledger_total(&c[0].ledger)
+    };
+    // Changing the same pedal back and forth is easier given more time.
+    assert!(cost(Some(4 * QUARTER)) < cost(Some(QUARTER / 4)));
+    assert_eq!(cost(None), cost(Some(QUARTER)));
+}
+
+#[test]
+fn reads_tempo() {
+    let parsed = parse("\\tempo 4 = 60 [a] [b]8 | \\tempo 8=240 [c]").unwrap();
+    assert_eq!(
+        per_beat(&parsed, |b| b.millis),
+        vec![vec![Some(1000), Some(500)], vec![Some(250)]]
+    );
+    assert!(parse("\\tempo 4 = 0 [a]").is_err());
+    let untimed = parse("[a] [b]8").unwrap();
+    assert!(untimed.music.iter().flatten().all(|b| b.millis.is_none()));
+}
+
+#[test]
+fn prunes_changes_too_fast_to_make() {
+    let beat = |n: &str, millis: Option<usize>| BeatInput {
+        duration: QUARTER / 4,
+        sustain: QUARTER / 4,
+        millis,
+        ..this(&[n])
+    };
+    let timed = |millis: Option<usize>| MusicInput {
+        diagram: [None; 7],
+        music: ["F", "F#", "F", "F#"].map(|n| beat(n, millis)).to_vec(),
+        goal: [None; 7],
+    };
+    let input = |millis: usize| timed(Some(millis));
+    let config = SolverConfig::default();
+    // Sixteenths at 160 bpm.
+    assert!(find_candidates(&input(93), &config).unwrap().is_empty());
+    assert!(!find_candidates(&input(250), &config).unwrap().is_empty());
+    let nimble = SolverConfig {
+        min_change_time: 50,
+        ..config
+    };
+    assert!(!find_candidates(&input(93), &nimble).unwrap().is_empty());
+    // Without a tempo, nothing is too fast.
+    assert!(!find_candidates(&timed(None), &config).unwrap().is_empty());
+}
+
+#[test]
+fn reads_octaves() {
+    let parsed = parse("[c' Eb3 *g,] [cb1]").unwrap();
+    let p = |n: &str, o: u8| Pitch::new(read_note(n), Some(o));
+    assert_eq!(
+        parsed.music[0][0].pitches,
+        (vec![p("G", 2)], vec![p("C", 4), p("Eb", 3)])
+    );
+    let e = parse("[g7 a7]").err().unwrap();
+    assert_eq!((e.line, e.column), (1, 5));
+    assert!(parse("[c8]").is_err());
 }
 
 #[test]
-fn can_have_none() {
-    let music: Vec<Vec<Note>> =
-        vec![vec!["F", "G"], vec!["F#", "G#"], vec!["Gb"]]
+fn crossed_strings_apart_are_harmless() {
+    let crossed = |e: u8, f: u8| {
+        let fixed = vec![
+            Pitch::new(read_note("E#"), Some(e)),
+            Pitch::new(read_note("Fb"), Some(f)),
+        ];
+        let input = MusicInput {
+            diagram: [None; 7],
+            music: vec![BeatInput {
+                pitches: (fixed.clone(), vec![]),
+                ..BeatInput::new(fixed.iter().map(|p| p.note).collect(), vec![])
+            }],
+            goal: [None; 7],
+        };
+        solve(&input)[0]
+            .ledger
             .iter()
-            .map(|v| v.iter().map(|n| read_note(n)).collect())
-            .collect();
-    let pedals: Vec<Vec<Note>> = vec![vec![], vec!["A"], vec!["F"]]
-        .iter()
-        .map(|v| v.iter().map(|n| read_note(n)).collect())
-        .collect();
-    let input: Vec<(Vec<Note>, Vec<Note>)> =
-        music.into_iter().zip(pedals).collect();
-    let shifted = unravel_paths(change_builder(&input, &[]));
-    assert!(shifted[0][2].is_none());
+            .any(|i| i.kind == CostKind::CrossedString && i.beat == 0)
+    };
+    assert!(crossed(4, 4));
+    assert!(!crossed(3, 5));
+}
+
+#[test]
+fn reads_damps() {
+    let parsed = parse("[a /] [b] | [/]").unwrap();
+    assert_eq!(
+        per_beat(&parsed, |b| b.damp),
+        vec![vec![true, false], vec![true]]
+    );
+    assert_eq!(parsed.music[0][0].this_any, (vec![], pcs(&["A"])));
+}
+
+#[test]
+fn warns_of_buzz() {
+    let buzzes = |let_ring: bool, decay: usize, damp: bool| {
+        let input = MusicInput {
+            music: vec![
+                this(&["F"]),
+                BeatInput {
+                    damp,
+                    ..this(&["F#"])
+                },
+            ],
+            ..Default::default()
+        };
+        let config = SolverConfig {
+            let_ring,
+            decay,
+            ..SolverConfig::default()
+        };
+        let c = find_candidates(&input, &config).unwrap();
+        c[0].ledger.iter().any(|i| i.kind == CostKind::Buzz)
+    };
+    assert!(buzzes(true, 0, false));
+    assert!(!buzzes(true, 0, true));
+    assert!(buzzes(false, DECAY, false));
+    assert!(!buzzes(false, DECAY, true));
+    assert!(!buzzes(false, 0, false));
+    // Written .hrp durations ring on too, unless damped.
+    let buzz = |s: &str| ledger_total(&solve_hrp(s)[0].ledger) >= BUZZ_COST;
+    assert!(buzz("[*f]8 [*f#]8"));
+    assert!(!buzz("[*f]8 [r]4 [*f#]8"));
+    assert!(!buzz("[*f]8 [/ *f#]8"));
+}
+
+#[test]
+fn reads_directives() {
+    let parsed = parse("{F#} [a] {!L} \\tempo 4 = 60 [b] | {!} [c]").unwrap();
+    let d = per_beat(&parsed, |b| b.directives.clone());
+    assert_eq!(d[0][0].changes, notes(&["F#"]));
+    assert!(d[0][1].hold_left && !d[0][1].hold_right);
+    assert!(d[1][0].hold_left && d[1][0].hold_right);
+    assert_eq!(parsed.music[0][1].millis, Some(1000));
+    assert!(parse("{F# Fb} [a]").is_err());
+    assert!(parse("{!R g} [a]").is_err());
+}
+
+#[test]
+fn obeys_directives() {
+    let plan = |directives: Vec<Directives>| {
+        let mut music = vec![this(&["F"]), this(&["G"]), this(&["F#"])];
+        for (beat, d) in music.iter_mut().zip(directives) {
+            beat.directives = d;
+        }
+        solve(&MusicInput {
+            music,
+            ..Default::default()
+        })
+    };
+    let free = plan(vec![]);
+    assert!(free[0].pedals[2].contains(&read_note("F#")));
+    let pin = Directives {
+        changes: notes(&["F#"]),
+        ..Default::default()
+    };
+    let pinned = plan(vec![Directives::default(), pin]);
+    assert!(pinned[0].pedals[1].contains(&read_note("F#")));
+    let hold = Directives {
+        hold_right: true,
+        ..Default::default()
+    };
+    let held = plan(vec![Directives::default(), hold.clone(), hold]);
+    assert!(held.is_empty());
+    // C# on the left mustn't stop F# being pinned on the right.
+    let c = solve_hrp("-v-|---- [d] {F#} [d] [d]");
+    assert_eq!(c[0].pedals.concat(), notes(&["F#"]));
+}
+
+#[test]
+fn reads_checkpoints() {
+    let parsed = parse("[a] | -v-|---- [b] [c] | ^^^|^^^^").unwrap();
+    let checkpoints = per_beat(&parsed, |b| b.checkpoint);
+    assert_eq!(checkpoints[0], vec![None]);
+    assert_eq!(
+        checkpoints[1],
+        vec![
+            Some([
+                Some(Natural),
+                Some(Sharp),
+                Some(Natural),
+                Some(Natural),
+                Some(Natural),
+                Some(Natural),
+                Some(Natural)
+            ]),
+            None
+        ]
+    );
+    assert_eq!(parsed.end, Some([Some(Flat); 7]));
+}
+
+#[test]
+fn passes_through_checkpoints() {
+    let plan = |checkpoint: Option<Harp>| {
+        solve(&MusicInput {
+            music: vec![
+                this(&["F"]),
+                BeatInput {
+                    checkpoint,
+                    ..this(&["G"])
+                },
+                this(&["F#"]),
+            ],
+            ..Default::default()
+        })
+    };
+    let mut sharp = [None; 7];
+    set_pedal(&mut sharp, read_note("F#"));
+    let c = plan(Some(sharp));
+    assert!(c[0].pedals[1].contains(&read_note("F#")));
+    let mut flat = [None; 7];
+    set_pedal(&mut flat, read_note("Gb"));
+    assert!(plan(Some(flat)).is_empty());
+}
+
+#[test]
+fn checkpoints_cost_what_the_ledger_says() {
+    let parsed = parse("[d] | ---|---- [d] [f#] |").unwrap();
+    let input = MusicInput::from(&parsed);
+    // Only D was played, so nothing else rings into the F# change.
+    let config = SolverConfig {
+        let_ring: true,
+        ..Default::default()
+    };
+    let chords = get_chords(&input).unwrap();
+    let (_, score) =
+        find_solutions(input.diagram, &chords, input.goal, &config);
+    let c = find_candidates(&input, &config).unwrap();
+    assert_eq!(ledger_total(&c[0].ledger), score);
+    assert_eq!(score, config.pedal_cost);
+}
+
+#[test]
+fn reads_sections() {
+    let sections = parse_sections(
+        "\\section \"Prelude\"\n---|----\n[a] [b] | [f#]\n\
+         \\section \"Fugue\"\n[f] [g]\n^^^|^^^^",
+    )
+    .unwrap();
+    assert_eq!(sections.len(), 2);
+    assert_eq!(sections[0].title.as_deref(), Some("Prelude"));
+    assert_eq!(sections[0].parsed.start, Some([Some(Natural); 7]));
+    assert_eq!(sections[0].parsed.end, None);
+    assert_eq!(sections[1].title.as_deref(), Some("Fugue"));
+    assert_eq!(sections[1].parsed.music.len(), 1);
+    assert_eq!(sections[1].parsed.end, Some([Some(Flat); 7]));
+    let untitled = parse_sections("[a] | [b]").unwrap();
+    assert_eq!(untitled.len(), 1);
+    assert_eq!(untitled[0].title, None);
+    assert!(parse_sections("\\section Prelude [a]").is_err());
+}
+
+#[test]
+fn flips_levers_only_in_rests() {
+    let plan = |music: &str| {
+        let parsed = parse(music).unwrap();
+        let input = MusicInput::from(&parsed);
+        find_lever_plan(&input, EB_TUNING, &SolverConfig::default())
+    };
+    let p = plan("[c4 e4] [f#4] [c3] [f4]").unwrap().unwrap();
+    assert_eq!(p.cost, LEVER_COST);
+    assert_eq!(p.setup.len(), 2);
+    // The right hand rests while the left plays c3.
+    let f4 = Pitch::new(read_note("F"), Some(4));
+    assert_eq!(p.flips, vec![vec![], vec![], vec![f4], vec![]]);
+    let p = plan("[f#4] [r] [c4] [f4]").unwrap().unwrap();
+    assert_eq!(p.flips, vec![vec![], vec![f4], vec![], vec![]]);
+    assert_eq!(plan("[c4 e4] [f#4] [f4]").unwrap(), None);
+    // Eb tuning has no Cb, and no telling which lever without an octave.
+    assert_eq!(plan("[*cb4]").unwrap(), None);
+    assert!(plan("[c]").is_err());
+}
+
+#[test]
+fn gliss_sounds_only_chord_tones() {
+    let dim = assign_gliss(&[], &[pc("C"), pc("Eb"), pc("F#"), pc("A")]);
+    assert_eq!(
+        dim,
+        Some(vec![notes_to_harp(
+            &["D#", "C", "B#", "Eb", "F#", "Gb", "A"].map(read_note)
+        )])
+    );
+    let major = [pc("C"), pc("E"), pc("G")];
+    assert_eq!(assign_gliss(&[], &major), None);
+    let conflict = explain_gliss(&[], &major).unwrap();
+    assert_eq!(conflict.strings, vec![Name::D, Name::A]);
+    let parsed = parse("gliss[c d e g a] [c]").unwrap();
+    assert_eq!(per_beat(&parsed, |b| b.gliss), vec![vec![true, false]]);
+    // Doubled strings are free in a glissando, crossed ones are not.
+    let harp =
+        notes_to_harp(&["D", "C", "B#", "Fb", "E#", "G", "A"].map(read_note));
+    let beat = Beat::new(
+        vec![harp],
+        QUARTER,
+        None,
+        None,
+        0,
+        false,
+        Directives::default(),
+    );
+    let config = SolverConfig::default();
+    let kinds = |beat: Beat| {
+        let ledger =
+            plan_ledger(harp, &[harp], &[beat], &vec![vec![]], &config);
+        ledger.iter().map(|i| i.kind).collect_vec()
+    };
+    assert_eq!(
+        kinds(beat.clone()),
+        [CostKind::DoubledString, CostKind::CrossedString]
+    );
+    assert_eq!(kinds(beat.with_gliss(true)), [CostKind::CrossedString]);
+}
+
+#[test]
+fn bisb_doubles_without_cost() {
+    let parsed = parse("[bisb(eb4) g] [bisb(b)]").unwrap();
+    let beat = &parsed.music[0][0];
+    assert_eq!(beat.this_any.0, ["D#", "Eb"].map(read_note));
+    assert_eq!(beat.bisb, pcs(&["Eb"]));
+    assert_eq!(beat.pitches.0[0], Pitch::new(read_note("D#"), Some(4)));
+    assert!(parse("[bisb(a)]").is_err());
+    let c = solve(&MusicInput::from(&parsed));
+    // B and Cb are charged before their bisbigliando, D# and Eb after theirs.
+    let doubled = |beat: usize| {
+        c[0].ledger
+            .iter()
+            .filter(|i| i.kind == CostKind::DoubledString && i.beat == beat)
+            .flat_map(|i| i.notes.clone())
+            .collect_vec()
+    };
+    assert_eq!(doubled(0), ["Cb", "B"].map(read_note));
+    assert_eq!(doubled(1), ["D#", "Eb"].map(read_note));
+}
+
+#[test]
+fn reads_and_writes_json() {
+    let json = r#"{
+        "start": ["Flat", null, "Natural", null, null, "Sharp", null],
+        "measures": [[
+            {"notes": [{"This": {"note": "Eb", "octave": 4}}], "duration": 256},
+            {"notes": [{"Any": {"note": "F#", "octave": null}}, "Rest"]}
+        ]]
+    }"#;
+    let parsed = parse_json(json).unwrap();
+    assert_eq!(parsed.start.unwrap()[0], Some(Flat));
+    assert_eq!(
+        per_beat(&parsed, |b| b.duration),
+        vec![vec![QUARTER, QUARTER]]
+    );
+    assert_eq!(
+        parsed.music[0][1].pitches.1,
+        vec![Pitch::new(read_note("F#"), None)]
+    );
+    assert!(parse_json(r#"{"measures": [[{"notes": ["Nope"]}]]}"#).is_err());
+    let c = solve(&MusicInput::from(&parsed));
+    let plan = JsonPlan::from(&c[0]);
+    let (left, right) = unzip_pedals(&c[0].pedals);
+    assert_eq!((plan.left, plan.right), (left, right));
+    assert_eq!(plan.cost, ledger_total(&plan.breakdown));
+    let text = serde_json::to_string(&JsonPlan::from(&c[0])).unwrap();
+    assert_eq!(
+        serde_json::from_str::<JsonPlan>(&text).unwrap().cost,
+        c[0].cost
+    );
+}
+
+#[test]
+fn verifies_hand_written_plans() {
+    let parsed = parse("---|---- [d f a] [bb d f] | [d f# a] [e#]").unwrap();
+    let input = MusicInput::from(&parsed);
+    let config = SolverConfig::default();
+    let c = find_candidates(&input, &config).unwrap();
+    let own = verify_plan(&input, c[0].diagram, &c[0].pedals, &config).unwrap();
+    assert!(own.problems.is_empty());
+    assert_eq!(own.cost, ledger_total(&c[0].ledger));
+
+    let pedals = vec![vec![], notes(&["Bb"]), notes(&["F#", "Fb"]), vec![]];
+    let bad = verify_plan(&input, c[0].diagram, &pedals, &config).unwrap();
+    assert!(!bad.is_playable());
+    assert_eq!(
+        bad.problems,
+        vec![
+            (2, Problem::TwoChanges(Foot::Right, notes(&["F#", "Fb"]))),
+            (2, Problem::Missing(notes(&["F#"]))),
+            (3, Problem::Missing(notes(&["E#"]))),
+            (3, Problem::Doubled(notes(&["E", "Fb"]))),
+            (4, Problem::Doubled(notes(&["E", "Fb"]))),
+        ]
+    );
+
+    // Checked against the best without its changes, but with its holds.
+    let parsed = parse("---|---- {!L} [d] {F#} [f#]").unwrap();
+    let free = without_written_changes(&MusicInput::from(&parsed));
+    assert!(free.music[0].directives.hold_left);
+    assert!(free.music.iter().all(|b| b.directives.changes.is_empty()));
+}
+
+#[test]
+fn annotates_the_source() {
+    let source = "[c e g] $ C major\n[c eb g] |\n[b d f#]\n";
+    let c = solve_hrp(source);
+    let annotated = annotate(source, &[&c[0]]).unwrap();
+    assert!(annotated.contains("$ C major\n"));
+    assert_eq!(annotated.lines().count(), 5);
+    assert!(!annotated.contains("[c"));
+    let again = solve_hrp(&annotated);
+    assert_eq!(again[0].pedals, c[0].pedals);
+    assert_eq!(again[0].spelling, c[0].spelling);
+    assert_eq!(annotate(&annotated, &[&again[0]]).unwrap(), annotated);
+    // A bare --annotate leaves the input file alone.
+    let cli = Cli::try_parse_from(["hps", "--annotate", "in.hrp"]).unwrap();
+    assert_eq!(cli.annotate, Some(None));
+    assert_eq!(cli.file, PathBuf::from("in.hrp"));
+    let cli =
+        Cli::try_parse_from(["hps", "--annotate=out.hrp", "in.hrp"]).unwrap();
+    assert_eq!(cli.annotate, Some(Some(PathBuf::from("out.hrp"))));
+}
+
+#[test]
+fn searches_every_start() {
+    let parsed = parse("[c e g] [bb d f] | [b d# f#] [c eb g]").unwrap();
+    let mut input = MusicInput::from(&parsed);
+    let config = SolverConfig::default();
+    let free = find_candidates(&input, &config).unwrap();
+    input.diagram = free[0].diagram;
+    let fixed = find_candidates(&input, &config).unwrap();
+    assert_eq!(
+        ledger_total(&free[0].ledger),
+        ledger_total(&fixed[0].ledger)
+    );
+}
+
+#[test]
+fn keeps_tied_starts() {
+    let input = MusicInput {
+        music: vec![this(&["C"])],
+        ..Default::default()
+    };
+    let chords = get_chords(&input).unwrap();
+    let mut start = [Some(Natural); 7];
+    start[0] = None;
+    let config = SolverConfig::default();
+    let (paths, _) = find_solutions(start, &chords, [None; 7], &config);
+    let ds = paths.iter().map(|p| p[0].pedals.get(0)).unique().count();
+    assert_eq!(ds, 3);
+}
+
+#[test]
+fn packs_harps() {
+    let settings = [None, Some(Flat), Some(Natural), Some(Sharp)];
+    let harps = (0..7)
+        .map(|_| settings)
+        .multi_cartesian_product()
+        .map(|v| -> Harp { v.try_into().unwrap() })
+        .collect_vec();
+    for a in &harps {
+        let p = PackedHarp::from(*a);
+        assert_eq!(Harp::from(p), *a);
+        assert_eq!(p.num_same(), num_same(*a));
+        assert_eq!(p.num_crossed(), num_crossed(*a));
+    }
+    for (a, b) in harps.iter().step_by(97).tuple_combinations() {
+        let (p, q) = (PackedHarp::from(*a), PackedHarp::from(*b));
+        assert_eq!(Harp::from(p.update(q)), update_harp(*a, *b));
+        assert_eq!(p.changes(q, ALL_PEDALS), harp_changes(*a, *b, 0..=6));
+        assert_eq!(p.num_changes(q, LEFT_PEDALS), num_changes(*a, *b, 0..=2));
+        assert_eq!(p.num_changes(q, RIGHT_PEDALS), num_changes(*a, *b, 3..=6));
+    }
+}
+
+#[test]
+fn plans_in_windows() {
+    let parsed = parse(
+        "[c e g] | [bb d f] | [b d# f#] | [c eb g] | [db f ab] | [c e g]",
+    )
+    .unwrap();
+    let input = MusicInput::from(&parsed);
+    let lengths = parsed.music.iter().map(|m| m.len()).collect_vec();
+    // Every change here would buzz if the chords rang on past their beats.
+    let config = SolverConfig {
+        decay: 0,
+        ..SolverConfig::default()
+    };
+    let best =
+        ledger_total(&find_candidates(&input, &config).unwrap()[0].ledger);
+    let whole = Window {
+        measures: 6,
+        overlap: 1,
+    };
+    let c = find_windowed_candidates(&input, &lengths, whole, &config).unwrap();
+    assert_eq!(ledger_total(&c[0].ledger), best);
+    let small = Window {
+        measures: 2,
+        overlap: 1,
+    };
+    let c = find_windowed_candidates(&input, &lengths, small, &config).unwrap();
+    assert_eq!(c[0].spelling.len(), input.music.len());
+    assert_eq!(c[0].pedals.len(), input.music.len() + 1);
+    assert!(ledger_total(&c[0].ledger) >= best);
+    // A measure past what is committed is look-ahead enough here.
+    let longer = Window {
+        measures: 3,
+        overlap: 1,
+    };
+    let c =
+        find_windowed_candidates(&input, &lengths, longer, &config).unwrap();
+    assert_eq!(ledger_total(&c[0].ledger), best);
+    // Committing to C♮ leaves the held left foot no way to C♯ or D♭.
+    let parsed = parse("[c] | {!L} [e] | {!L} [e] | {!L} [c#]").unwrap();
+    let input = MusicInput::from(&parsed);
+    let lengths = parsed.music.iter().map(|m| m.len()).collect_vec();
+    let stuck = find_windowed_candidates(&input, &lengths, small, &config);
+    assert_eq!(stuck.err(), Some(WindowError::Stuck(2)));
 }